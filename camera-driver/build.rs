@@ -0,0 +1,42 @@
+// With the svbony feature, generates the bindings of SVBCameraSDK.h and links
+// libSVBCameraSDK. Set SVBONY_SDK_INCLUDE_DIR and SVBONY_SDK_LIB_DIR when the SDK
+// is not installed in the default include and library directories.
+// bindgen needs libclang.
+fn main() {
+    #[cfg(feature = "svbony")]
+    svbony();
+}
+
+#[cfg(feature = "svbony")]
+fn svbony() {
+    use std::env;
+    use std::path::PathBuf;
+
+    println!("cargo:rerun-if-changed=wrapper.h");
+    println!("cargo:rerun-if-env-changed=SVBONY_SDK_INCLUDE_DIR");
+    println!("cargo:rerun-if-env-changed=SVBONY_SDK_LIB_DIR");
+
+    let mut builder = bindgen::Builder::default()
+        .header("wrapper.h")
+        .allowlist_function("SVB.*")
+        .allowlist_type("SVB_.*")
+        .allowlist_var("SVB_.*")
+        .derive_debug(true)
+        .derive_copy(true)
+        .parse_callbacks(Box::new(bindgen::CargoCallbacks::new()));
+    if let Ok(dir) = env::var("SVBONY_SDK_INCLUDE_DIR") {
+        builder = builder.clang_arg(format!("-I{}", dir));
+    }
+    let bindings = builder
+        .generate()
+        .expect("failed to generate the bindings of SVBCameraSDK.h");
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("libsvb.rs");
+    bindings
+        .write_to_file(out)
+        .expect("failed to write the bindings of SVBCameraSDK.h");
+
+    if let Ok(dir) = env::var("SVBONY_SDK_LIB_DIR") {
+        println!("cargo:rustc-link-search=native={}", dir);
+    }
+    println!("cargo:rustc-link-lib=dylib=SVBCameraSDK");
+}
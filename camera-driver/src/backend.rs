@@ -0,0 +1,56 @@
+use crate::interface::{CameraInterface, CameraResult, DeviceDescriptor};
+use crate::mock::MockBackend;
+#[cfg(feature = "svbony")]
+use crate::svb_camera::SvbBackend;
+
+/// Enumerates and opens the cameras of one vendor.
+pub trait CameraBackend: Send + Sync {
+    /// Name of the backend in the configuration and the inventory, e.g. "svbony".
+    fn name(&self) -> &'static str;
+    /// Enumerate the connected cameras without opening them.
+    fn enumerate(&self) -> CameraResult<Vec<DeviceDescriptor>>;
+    /// Open a camera returned by the last call to `enumerate`.
+    fn open(&self, device: &DeviceDescriptor) -> CameraResult<Box<dyn CameraInterface + Send>>;
+}
+
+/// The camera backends known to the server, in registration order.
+#[derive(Default)]
+pub struct BackendRegistry {
+    backends: Vec<Box<dyn CameraBackend>>,
+}
+
+impl BackendRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registry holding every backend built into this crate : the mock backend
+    /// (without cameras), and the SVBONY backend with the `svbony` feature.
+    pub fn builtin() -> Self {
+        let mut registry = Self::new();
+        registry.register(Box::new(MockBackend::default()));
+        #[cfg(feature = "svbony")]
+        registry.register(Box::new(SvbBackend));
+        registry
+    }
+
+    /// Add a backend, replacing a registered backend of the same name.
+    pub fn register(&mut self, backend: Box<dyn CameraBackend>) {
+        match self.backends.iter().position(|b| b.name() == backend.name()) {
+            Some(idx) => self.backends[idx] = backend,
+            None => self.backends.push(backend),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn CameraBackend> {
+        self.iter().find(|backend| backend.name() == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn CameraBackend> {
+        self.backends.iter().map(|backend| backend.as_ref())
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.iter().map(|backend| backend.name()).collect()
+    }
+}
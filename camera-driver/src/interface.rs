@@ -1,9 +1,54 @@
 use serde::{Deserialize, Serialize};
 
-use log::{debug, error, info, warn};
-use svbony_camera_rs::libsvb;
+use std::fmt;
+#[cfg(feature = "svbony")]
+use crate::svbony::libsvb;
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// Errors reported by camera backends.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CameraError {
+    /// No camera is connected at the requested index, or it has been unplugged.
+    DeviceNotFound(usize),
+    /// The device did not deliver a frame or answer a request in time.
+    Timeout,
+    /// A parameter is outside of what the device accepts.
+    InvalidArgument(String),
+    /// The operation is not available on this device.
+    Unsupported(String),
+    /// The vendor SDK returned a non-success error code.
+    Sdk(i32),
+}
+
+impl fmt::Display for CameraError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CameraError::DeviceNotFound(idx) => write!(f, "camera {} not found", idx),
+            CameraError::Timeout => write!(f, "camera timed out"),
+            CameraError::InvalidArgument(msg) => write!(f, "invalid argument: {}", msg),
+            CameraError::Unsupported(msg) => write!(f, "unsupported operation: {}", msg),
+            CameraError::Sdk(code) => write!(f, "camera SDK error code {}", code),
+        }
+    }
+}
+
+impl CameraError {
+    /// Short machine readable identifier of the error kind.
+    pub fn code(&self) -> &'static str {
+        match self {
+            CameraError::DeviceNotFound(_) => "device_not_found",
+            CameraError::Timeout => "timeout",
+            CameraError::InvalidArgument(_) => "invalid_argument",
+            CameraError::Unsupported(_) => "unsupported",
+            CameraError::Sdk(_) => "sdk_error",
+        }
+    }
+}
+
+impl std::error::Error for CameraError {}
+
+pub type CameraResult<T> = Result<T, CameraError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ImgType {
     RAW8 = 0,
     RAW10,
@@ -21,8 +66,42 @@ pub enum ImgType {
 }
 
 impl ImgType {
+    /// Number of significant bits per sample (per channel for RGB types).
+    pub fn bit_depth(&self) -> u8 {
+        match self {
+            ImgType::RAW8 | ImgType::Y8 | ImgType::RGB24 | ImgType::RGB32 => 8,
+            ImgType::RAW10 | ImgType::Y10 => 10,
+            ImgType::RAW12 | ImgType::Y12 => 12,
+            ImgType::RAW14 | ImgType::Y14 => 14,
+            ImgType::RAW16 | ImgType::Y16 => 16,
+            ImgType::END => 0,
+        }
+    }
+    /// Layout of the pixels delivered for this image type.
+    pub fn pixel_format(&self) -> PixelFormat {
+        match self {
+            ImgType::RAW8 | ImgType::RAW10 | ImgType::RAW12 | ImgType::RAW14 | ImgType::RAW16 => {
+                PixelFormat::Raw
+            }
+            ImgType::Y8 | ImgType::Y10 | ImgType::Y12 | ImgType::Y14 | ImgType::Y16 => {
+                PixelFormat::Mono
+            }
+            ImgType::RGB24 => PixelFormat::Bgr24,
+            ImgType::RGB32 => PixelFormat::Bgra32,
+            ImgType::END => PixelFormat::Unknown,
+        }
+    }
+    /// Number of bytes used by one pixel in a frame buffer.
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self.pixel_format() {
+            PixelFormat::Raw | PixelFormat::Mono if self.bit_depth() > 8 => 2,
+            PixelFormat::Raw | PixelFormat::Mono => 1,
+            PixelFormat::Bgr24 => 3,
+            PixelFormat::Bgra32 => 4,
+            PixelFormat::Unknown => 0,
+        }
+    }
     pub fn from_i32(img_t: &i32) -> ImgType {
-        println!("img_type_str: {}", img_t);
         match img_t {
             0 => ImgType::RAW8,
             1 => ImgType::RAW10,
@@ -41,6 +120,7 @@ impl ImgType {
             _ => ImgType::END,
         }
     }
+    #[cfg(feature = "svbony")]
     pub fn to_svb(img_type: ImgType) -> libsvb::SVB_IMG_TYPE {
         match img_type {
             ImgType::RAW8 => libsvb::SVB_IMG_TYPE_SVB_IMG_RAW8,
@@ -60,7 +140,9 @@ impl ImgType {
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+// Variants are named after the SDK controls, the names are part of the typed protocol.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ControlType {
     GAIN = 0,
     EXPOSURE,
@@ -83,8 +165,8 @@ pub enum ControlType {
     BAD_PIXEL_CORRECTION_ENABLE,
 }
 impl ControlType {
-    pub fn from_i32(ctrl_idx: &i32) -> ControlType {
-        match ctrl_idx {
+    pub fn from_i32(ctrl_idx: &i32) -> CameraResult<ControlType> {
+        let ctrl_type = match ctrl_idx {
             0 => ControlType::GAIN,
             1 => ControlType::EXPOSURE,
             2 => ControlType::GAMMA,
@@ -105,13 +187,18 @@ impl ControlType {
             17 => ControlType::COOLER_POWER,
             18 => ControlType::BAD_PIXEL_CORRECTION_ENABLE,
             _ => {
-                panic!("Unknown control type");
+                return Err(CameraError::InvalidArgument(format!(
+                    "unknown control type {}",
+                    ctrl_idx
+                )));
             }
-        }
+        };
+        Ok(ctrl_type)
     }
 
+    #[cfg(feature = "svbony")]
     pub fn to_svb(ctrl_type: ControlType) -> libsvb::SVB_CONTROL_TYPE {
-        let svb_ctrl_t = match ctrl_type {
+        match ctrl_type {
             ControlType::CONTRAST => libsvb::SVB_CONTROL_TYPE_SVB_CONTRAST,
             ControlType::CURRENT_TEMPERATURE => libsvb::SVB_CONTROL_TYPE_SVB_CURRENT_TEMPERATURE,
             ControlType::COOLER_POWER => libsvb::SVB_CONTROL_TYPE_SVB_COOLER_POWER,
@@ -135,9 +222,38 @@ impl ControlType {
             ControlType::BAD_PIXEL_CORRECTION_ENABLE => {
                 libsvb::SVB_CONTROL_TYPE_SVB_BAD_PIXEL_CORRECTION_ENABLE
             }
-        };
+        }
+    }
 
-        svb_ctrl_t
+    #[cfg(feature = "svbony")]
+    pub fn from_svb(svb_ctrl_t: libsvb::SVB_CONTROL_TYPE) -> Option<ControlType> {
+        let ctrl_type = match svb_ctrl_t {
+            libsvb::SVB_CONTROL_TYPE_SVB_GAIN => ControlType::GAIN,
+            libsvb::SVB_CONTROL_TYPE_SVB_EXPOSURE => ControlType::EXPOSURE,
+            libsvb::SVB_CONTROL_TYPE_SVB_GAMMA => ControlType::GAMMA,
+            libsvb::SVB_CONTROL_TYPE_SVB_GAMMA_CONTRAST => ControlType::GAMMA_CONTRAST,
+            libsvb::SVB_CONTROL_TYPE_SVB_WB_R => ControlType::WB_R,
+            libsvb::SVB_CONTROL_TYPE_SVB_WB_G => ControlType::WB_G,
+            libsvb::SVB_CONTROL_TYPE_SVB_WB_B => ControlType::WB_B,
+            libsvb::SVB_CONTROL_TYPE_SVB_FLIP => ControlType::FLIP,
+            libsvb::SVB_CONTROL_TYPE_SVB_FRAME_SPEED_MODE => ControlType::FRAME_SPEED_MODE,
+            libsvb::SVB_CONTROL_TYPE_SVB_CONTRAST => ControlType::CONTRAST,
+            libsvb::SVB_CONTROL_TYPE_SVB_SHARPNESS => ControlType::SHARPNESS,
+            libsvb::SVB_CONTROL_TYPE_SVB_SATURATION => ControlType::SATURATION,
+            libsvb::SVB_CONTROL_TYPE_SVB_AUTO_TARGET_BRIGHTNESS => {
+                ControlType::AUTO_TARGET_BRIGHTNESS
+            }
+            libsvb::SVB_CONTROL_TYPE_SVB_BLACK_LEVEL => ControlType::BLACK_LEVEL,
+            libsvb::SVB_CONTROL_TYPE_SVB_COOLER_ENABLE => ControlType::COOLER_ENABLE,
+            libsvb::SVB_CONTROL_TYPE_SVB_TARGET_TEMPERATURE => ControlType::TARGET_TEMPERATURE,
+            libsvb::SVB_CONTROL_TYPE_SVB_CURRENT_TEMPERATURE => ControlType::CURRENT_TEMPERATURE,
+            libsvb::SVB_CONTROL_TYPE_SVB_COOLER_POWER => ControlType::COOLER_POWER,
+            libsvb::SVB_CONTROL_TYPE_SVB_BAD_PIXEL_CORRECTION_ENABLE => {
+                ControlType::BAD_PIXEL_CORRECTION_ENABLE
+            }
+            _ => return None,
+        };
+        Some(ctrl_type)
     }
 }
 
@@ -152,7 +268,7 @@ pub struct CameraInfo {
     pub is_coolable: bool,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ROIFormat {
     pub startx: u32,
     pub starty: u32,
@@ -162,20 +278,76 @@ pub struct ROIFormat {
     pub img_type: u8,
 }
 
+/// A connected camera as reported by `CameraBackend::enumerate`, before it is opened.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceDescriptor {
+    /// Position in the enumeration, only valid until the next one.
+    pub idx: usize,
+    pub name: String,
+    /// Identifies the device across enumerations.
+    pub serial: String,
+}
+
+/// Pixel layout of a frame buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PixelFormat {
+    /// Sensor data before debayering (bayer pattern on color sensors), one sample per pixel.
+    Raw,
+    /// Luminance, one sample per pixel.
+    Mono,
+    /// Interleaved B, G, R bytes.
+    Bgr24,
+    /// Interleaved B, G, R, A bytes.
+    Bgra32,
+    Unknown,
+}
+
+/// A frame as delivered by the camera. Samples wider than 8 bits are
+/// stored in two little endian bytes.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub img_type: ImgType,
+    pub pixel_format: PixelFormat,
+    pub bit_depth: u8,
+}
+
+impl Frame {
+    pub fn new(data: Vec<u8>, width: u32, height: u32, img_type: ImgType) -> Self {
+        Frame {
+            data,
+            width,
+            height,
+            img_type,
+            pixel_format: img_type.pixel_format(),
+            bit_depth: img_type.bit_depth(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ControlCaps {
     pub name: String,
-    pub max_value: u64,
-    pub min_value: u64,
-    pub default_value: u64,
+    pub max_value: i64,
+    pub min_value: i64,
+    pub default_value: i64,
     pub is_auto_supported: bool,
     pub is_writable: bool,
     pub control_type: ControlType,
 }
 
+impl ControlCaps {
+    /// Limit `value` to the range supported by the control.
+    pub fn clamp(&self, value: i64) -> i64 {
+        value.clamp(self.min_value, self.max_value)
+    }
+}
+
+/// An opened camera. Cameras are opened by their `CameraBackend`, so the trait
+/// is object safe and the server handles every vendor as `dyn CameraInterface`.
 pub trait CameraInterface {
-    fn num_devices() -> usize;
-    fn new(idx: usize) -> Self;
     fn set_roi(
         &mut self,
         startx: u32,
@@ -184,19 +356,84 @@ pub trait CameraInterface {
         height: u32,
         bin: u8,
         img_type: ImgType,
-    );
-    fn set_img_type(&mut self, img_type: ImgType);
+    ) -> CameraResult<()>;
+    fn set_img_type(&mut self, img_type: ImgType) -> CameraResult<()>;
     fn get_roi(&self) -> ROIFormat;
-    fn get_img_type(&self) -> ImgType;
-    fn start_capture(&mut self);
-    fn stop_capture(&mut self);
-    fn get_frame(&self) -> String;
-    fn get_control_value(&self, ctrl_type: ControlType) -> i64;
-    fn set_control_value(&self, ctrl_type: ControlType, value: i64, is_auto: i64);
+    fn get_img_type(&self) -> CameraResult<ImgType>;
+    fn start_capture(&mut self) -> CameraResult<()>;
+    fn stop_capture(&mut self) -> CameraResult<()>;
+    /// Read the next frame in the current ROI and image type.
+    fn get_frame(&self) -> CameraResult<Frame>;
+    fn get_control_value(&self, ctrl_type: ControlType) -> CameraResult<i64>;
+    fn set_control_value(&self, ctrl_type: ControlType, value: i64, is_auto: i64)
+        -> CameraResult<()>;
     fn get_info(&self) -> CameraInfo;
+    fn get_control_caps(&self) -> CameraResult<Vec<ControlCaps>>;
     fn is_capture(&self) -> bool;
     fn set_is_capture(&mut self, is_capture: bool);
-    fn adjust_white_balance(&self);
-    
-    fn close(&self);
+    fn adjust_white_balance(&self) -> CameraResult<()>;
+
+    fn close(&self) -> CameraResult<()>;
+}
+
+impl<T: CameraInterface + ?Sized> CameraInterface for Box<T> {
+    fn set_roi(
+        &mut self,
+        startx: u32,
+        starty: u32,
+        width: u32,
+        height: u32,
+        bin: u8,
+        img_type: ImgType,
+    ) -> CameraResult<()> {
+        (**self).set_roi(startx, starty, width, height, bin, img_type)
+    }
+    fn set_img_type(&mut self, img_type: ImgType) -> CameraResult<()> {
+        (**self).set_img_type(img_type)
+    }
+    fn get_roi(&self) -> ROIFormat {
+        (**self).get_roi()
+    }
+    fn get_img_type(&self) -> CameraResult<ImgType> {
+        (**self).get_img_type()
+    }
+    fn start_capture(&mut self) -> CameraResult<()> {
+        (**self).start_capture()
+    }
+    fn stop_capture(&mut self) -> CameraResult<()> {
+        (**self).stop_capture()
+    }
+    fn get_frame(&self) -> CameraResult<Frame> {
+        (**self).get_frame()
+    }
+    fn get_control_value(&self, ctrl_type: ControlType) -> CameraResult<i64> {
+        (**self).get_control_value(ctrl_type)
+    }
+    fn set_control_value(
+        &self,
+        ctrl_type: ControlType,
+        value: i64,
+        is_auto: i64,
+    ) -> CameraResult<()> {
+        (**self).set_control_value(ctrl_type, value, is_auto)
+    }
+    fn get_info(&self) -> CameraInfo {
+        (**self).get_info()
+    }
+    fn get_control_caps(&self) -> CameraResult<Vec<ControlCaps>> {
+        (**self).get_control_caps()
+    }
+    fn is_capture(&self) -> bool {
+        (**self).is_capture()
+    }
+    fn set_is_capture(&mut self, is_capture: bool) {
+        (**self).set_is_capture(is_capture)
+    }
+    fn adjust_white_balance(&self) -> CameraResult<()> {
+        (**self).adjust_white_balance()
+    }
+
+    fn close(&self) -> CameraResult<()> {
+        (**self).close()
+    }
 }
@@ -1,3 +1,8 @@
+pub mod backend;
 pub mod interface;
 pub mod mock;
+pub mod roi;
+#[cfg(feature = "svbony")]
 pub mod svb_camera;
+#[cfg(feature = "svbony")]
+pub mod svbony;
@@ -1,48 +1,205 @@
-use crate::interface::{CameraInfo, CameraInterface, ControlType, ImgType, ROIFormat};
-use base64::{
-    alphabet,
-    engine::{self, general_purpose},
-    Engine as _,
+use crate::backend::CameraBackend;
+use crate::interface::{
+    CameraError, CameraInfo, CameraInterface, CameraResult, ControlCaps, ControlType,
+    DeviceDescriptor, Frame, ImgType, ROIFormat,
 };
+use crate::roi::validate_roi;
 use rand::Rng; // ランダムな値を生成するために使用
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
 use std::thread;
 use std::time::Duration;
-use tokio;
+
+// (control_type, min, max, default, is_auto_supported, is_writable)
+type MockControl = (ControlType, i64, i64, i64, bool, bool);
+
+const MOCK_CONTROL_CAPS: [MockControl; 13] = [
+    (ControlType::GAIN, 0, 720, 10, true, true),
+    (ControlType::EXPOSURE, 29, 2_000_000_000, 30_000, true, true),
+    (ControlType::GAMMA, 0, 1000, 100, false, true),
+    (ControlType::GAMMA_CONTRAST, 0, 1000, 100, false, true),
+    (ControlType::WB_R, 0, 1000, 128, true, true),
+    (ControlType::WB_G, 0, 1000, 128, true, true),
+    (ControlType::WB_B, 0, 1000, 128, true, true),
+    (ControlType::FLIP, 0, 3, 0, false, true),
+    (ControlType::FRAME_SPEED_MODE, 0, 2, 1, false, true),
+    (ControlType::CONTRAST, 0, 100, 50, false, true),
+    (ControlType::SHARPNESS, 0, 100, 0, false, true),
+    (ControlType::SATURATION, 0, 255, 128, false, true),
+    (ControlType::BLACK_LEVEL, 0, 255, 0, false, true),
+];
+
+// Added to MOCK_CONTROL_CAPS when the camera is coolable. Temperatures are in 0.1 °C.
+const MOCK_COOLER_CAPS: [MockControl; 4] = [
+    (ControlType::COOLER_ENABLE, 0, 1, 0, false, true),
+    (ControlType::TARGET_TEMPERATURE, -400, 300, 0, false, true),
+    (ControlType::CURRENT_TEMPERATURE, -500, 500, 200, false, false),
+    (ControlType::COOLER_POWER, 0, 100, 0, false, false),
+];
+
+// Controls of a camera built from `spec`.
+fn mock_controls(spec: &MockCameraSpec) -> impl Iterator<Item = &'static MockControl> {
+    let cooler: &[MockControl] = if spec.is_coolable {
+        &MOCK_COOLER_CAPS
+    } else {
+        &[]
+    };
+    MOCK_CONTROL_CAPS.iter().chain(cooler)
+}
+
+/// Properties of a simulated camera. Missing fields keep the default shown by `Default`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MockCameraSpec {
+    pub name: String,
+    /// Serial number, `MOCK<idx>` when missing.
+    pub serial: Option<String>,
+    pub max_width: u32,
+    pub max_height: u32,
+    pub supported_img_type: Vec<ImgType>,
+    pub supported_bins: Vec<u8>,
+    pub is_coolable: bool,
+}
+
+impl Default for MockCameraSpec {
+    fn default() -> Self {
+        Self {
+            name: "Mock Camera".to_string(),
+            serial: None,
+            max_width: 1912,
+            max_height: 1304,
+            supported_img_type: vec![ImgType::RAW8, ImgType::RAW16],
+            supported_bins: vec![1, 2, 4, 8],
+            is_coolable: false,
+        }
+    }
+}
+
+impl MockCameraSpec {
+    /// Serial number of the camera at `idx` in the mock camera list.
+    pub fn serial(&self, idx: usize) -> String {
+        self.serial
+            .clone()
+            .unwrap_or_else(|| format!("MOCK{:04}", idx))
+    }
+}
 
 // CameraInfo、ROIFormat、ImgType、ControlType、ControlCapsなどのデータ構造を適切に定義する必要があります
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct MockCamera {
     idx: usize,
-    w: u32,
-    h: u32,
+    spec: MockCameraSpec,
+    roi: ROIFormat,
     is_capture: bool,
-    // CameraInfoなどの初期化を追加する必要があります
+    // Current value of each control of mock_controls, starting at its default.
+    controls: Mutex<HashMap<ControlType, i64>>,
 }
 
-impl CameraInterface for MockCamera {
-    fn num_devices() -> usize {
-       0
-    }
-    fn new(idx: usize) -> Self {
-        MockCamera {
+impl MockCamera {
+    pub fn new(idx: usize, spec: MockCameraSpec) -> CameraResult<Self> {
+        let img_type = *spec.supported_img_type.first().ok_or_else(|| {
+            CameraError::InvalidArgument(format!("mock camera {} has no image type", idx))
+        })?;
+        // The whole sensor at the smallest bin, snapped to the SDK alignment by validate_roi.
+        let bin = spec.supported_bins.iter().copied().min().unwrap_or(1);
+        let mut camera = MockCamera {
             idx,
-            w: 1912,
-            h: 1304,
+            roi: ROIFormat {
+                startx: 0,
+                starty: 0,
+                width: spec.max_width,
+                height: spec.max_height,
+                bin,
+                img_type: img_type as u8,
+            },
+            controls: Mutex::new(
+                mock_controls(&spec)
+                    .map(|&(control_type, _, _, default_value, _, _)| (control_type, default_value))
+                    .collect(),
+            ),
+            spec,
             is_capture: false,
-            // CameraInfoなどの初期化を追加する必要があります
-        }
+        };
+        camera.roi = validate_roi(&camera.get_info(), &camera.roi)?;
+        Ok(camera)
+    }
+}
+
+fn unsupported_control(ctrl_type: ControlType) -> CameraError {
+    CameraError::Unsupported(format!(
+        "control {:?} is not available on this camera",
+        ctrl_type
+    ))
+}
+
+/// Backend of the simulated cameras, one camera per spec.
+#[derive(Debug, Clone, Default)]
+pub struct MockBackend {
+    cameras: Vec<MockCameraSpec>,
+}
+
+impl MockBackend {
+    pub fn new(cameras: Vec<MockCameraSpec>) -> Self {
+        Self { cameras }
     }
+}
+
+impl CameraBackend for MockBackend {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+    fn enumerate(&self) -> CameraResult<Vec<DeviceDescriptor>> {
+        Ok(self
+            .cameras
+            .iter()
+            .enumerate()
+            .map(|(idx, spec)| DeviceDescriptor {
+                idx,
+                name: spec.name.clone(),
+                serial: spec.serial(idx),
+            })
+            .collect())
+    }
+    fn open(&self, device: &DeviceDescriptor) -> CameraResult<Box<dyn CameraInterface + Send>> {
+        let spec = self
+            .cameras
+            .get(device.idx)
+            .ok_or(CameraError::DeviceNotFound(device.idx))?;
+        Ok(Box::new(MockCamera::new(device.idx, spec.clone())?))
+    }
+}
+
+impl CameraInterface for MockCamera {
     fn get_info(&self) -> CameraInfo {
         CameraInfo {
-            name: "Mock Camera".to_string(),
+            name: self.spec.name.clone(),
             idx: self.idx as u32,
-            max_width: 1912,
-            max_height: 1304,
-            supported_img_type: vec![ImgType::RAW8, ImgType::RAW16],
-            supported_bins: vec![1, 2, 4, 8],
-            is_coolable: false,
+            max_width: self.spec.max_width,
+            max_height: self.spec.max_height,
+            supported_img_type: self.spec.supported_img_type.clone(),
+            supported_bins: self.spec.supported_bins.clone(),
+            is_coolable: self.spec.is_coolable,
         }
     }
+    fn get_control_caps(&self) -> CameraResult<Vec<ControlCaps>> {
+        let caps = mock_controls(&self.spec)
+            .map(
+                |&(control_type, min_value, max_value, default_value, is_auto_supported, is_writable)| {
+                    ControlCaps {
+                        name: format!("{:?}", control_type),
+                        max_value,
+                        min_value,
+                        default_value,
+                        is_auto_supported,
+                        is_writable,
+                        control_type,
+                    }
+                },
+            )
+            .collect();
+        Ok(caps)
+    }
     fn set_roi(
         &mut self,
         startx: u32,
@@ -51,60 +208,82 @@ impl CameraInterface for MockCamera {
         height: u32,
         bin: u8,
         img_type: ImgType,
-    ) {
-        // set_roiメソッドの実装
+    ) -> CameraResult<()> {
+        let req = ROIFormat {
+            startx,
+            starty,
+            width,
+            height,
+            bin,
+            img_type: img_type as u8,
+        };
+        self.roi = validate_roi(&self.get_info(), &req)?;
+        Ok(())
     }
 
-    fn set_img_type(&mut self, img_type: ImgType) {
-        // set_img_typeメソッドの実装
+    fn set_img_type(&mut self, img_type: ImgType) -> CameraResult<()> {
+        let req = ROIFormat {
+            img_type: img_type as u8,
+            ..self.roi
+        };
+        self.roi = validate_roi(&self.get_info(), &req)?;
+        Ok(())
     }
 
     fn get_roi(&self) -> ROIFormat {
-        // roiの適当な要素を生成
-        ROIFormat {
-            startx: 0,
-            starty: 0,
-            width: self.w,
-            height: self.h,
-            bin: 1,
-            img_type: ImgType::RAW8 as u8,
-        }
+        self.roi
     }
 
-    fn get_img_type(&self) -> ImgType {
-        // imgtypの適当な要素を生成
-        ImgType::RAW8
+    fn get_img_type(&self) -> CameraResult<ImgType> {
+        Ok(ImgType::from_i32(&(self.roi.img_type as i32)))
     }
 
-    fn start_capture(&mut self) {
-        self.is_capture = true
+    fn start_capture(&mut self) -> CameraResult<()> {
+        self.is_capture = true;
+        Ok(())
     }
 
-    fn stop_capture(&mut self) {
-        self.is_capture = false
+    fn stop_capture(&mut self) -> CameraResult<()> {
+        self.is_capture = false;
+        Ok(())
     }
 
-    fn get_frame(&self) -> String {
+    fn get_frame(&self) -> CameraResult<Frame> {
+        // Like a real camera, a frame takes the current exposure (in microseconds).
+        let exposure = self.get_control_value(ControlType::EXPOSURE)?;
+        thread::sleep(Duration::from_micros(exposure.max(0) as u64));
+        let img_type = self.get_img_type()?;
+        let len = (self.roi.width * self.roi.height) as usize * img_type.bytes_per_pixel();
         let mut rng = rand::thread_rng();
-        let buf: Vec<u8> = (0..(self.w * self.h))
+        let buf: Vec<u8> = (0..len)
             .map(|_| rng.gen_range(0..255))
             .collect();
-        println!("=================== get_frame");
-        let num = rng.gen_range(0..5) * 100000;
-        for i in 0..num{}
-        println!("=================== end");
-
-        let buf = base64::encode(buf);
-        buf
+        Ok(Frame::new(buf, self.roi.width, self.roi.height, img_type))
     }
-    fn get_control_value(&self, ctrl_type: ControlType) -> i64 {
-        // get_control_valueメソッドの実装
-        0
+    fn get_control_value(&self, ctrl_type: ControlType) -> CameraResult<i64> {
+        self.controls
+            .lock()
+            .unwrap()
+            .get(&ctrl_type)
+            .copied()
+            .ok_or_else(|| unsupported_control(ctrl_type))
     }
-    fn adjust_white_balance(&self) {
-        
+    fn adjust_white_balance(&self) -> CameraResult<()> {
+        Ok(())
     }
-    fn set_control_value(&self, ctrl_type: ControlType, value: i64, is_auto: i64) {
+    fn set_control_value(
+        &self,
+        ctrl_type: ControlType,
+        value: i64,
+        _is_auto: i64,
+    ) -> CameraResult<()> {
+        match self.controls.lock().unwrap().get_mut(&ctrl_type) {
+            Some(current) => {
+                *current = value;
+                Ok(())
+            }
+            None => Err(unsupported_control(ctrl_type)),
+        }
     }
     fn is_capture(&self) -> bool {
         self.is_capture
@@ -113,7 +292,81 @@ impl CameraInterface for MockCamera {
         self.is_capture = is_capture
     }
 
-    fn close(&self) {
+    fn close(&self) -> CameraResult<()> {
         // closeメソッドの実装
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn initial_roi_is_aligned() {
+        let spec = MockCameraSpec {
+            max_width: 1913,
+            max_height: 1305,
+            ..MockCameraSpec::default()
+        };
+        let roi = MockCamera::new(0, spec).unwrap().get_roi();
+        assert_eq!((roi.startx, roi.starty, roi.width, roi.height), (0, 0, 1912, 1304));
+        assert_eq!(roi.bin, 1);
+    }
+
+    #[test]
+    fn control_values_start_at_their_default() {
+        let camera = MockCamera::new(0, MockCameraSpec::default()).unwrap();
+        assert_eq!(camera.get_control_value(ControlType::GAIN).unwrap(), 10);
+        assert_eq!(camera.get_control_value(ControlType::EXPOSURE).unwrap(), 30_000);
+    }
+
+    #[test]
+    fn set_control_value_reads_back() {
+        let camera = MockCamera::new(0, MockCameraSpec::default()).unwrap();
+        camera.set_control_value(ControlType::GAIN, 120, 0).unwrap();
+        assert_eq!(camera.get_control_value(ControlType::GAIN).unwrap(), 120);
+    }
+
+    #[test]
+    fn get_frame_waits_for_the_exposure() {
+        let camera = MockCamera::new(0, MockCameraSpec::default()).unwrap();
+        camera.set_control_value(ControlType::EXPOSURE, 50_000, 0).unwrap();
+        let start = std::time::Instant::now();
+        camera.get_frame().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn coolable_camera_has_cooler_controls() {
+        let spec = MockCameraSpec {
+            is_coolable: true,
+            ..MockCameraSpec::default()
+        };
+        let camera = MockCamera::new(0, spec).unwrap();
+        let caps = camera.get_control_caps().unwrap();
+        let writable = |control_type| {
+            caps.iter()
+                .find(|caps| caps.control_type == control_type)
+                .map(|caps| caps.is_writable)
+        };
+        assert_eq!(writable(ControlType::COOLER_ENABLE), Some(true));
+        assert_eq!(writable(ControlType::TARGET_TEMPERATURE), Some(true));
+        assert_eq!(writable(ControlType::CURRENT_TEMPERATURE), Some(false));
+        assert_eq!(writable(ControlType::COOLER_POWER), Some(false));
+        assert_eq!(camera.get_control_value(ControlType::CURRENT_TEMPERATURE).unwrap(), 200);
+    }
+
+    #[test]
+    fn controls_outside_the_table_are_unsupported() {
+        let camera = MockCamera::new(0, MockCameraSpec::default()).unwrap();
+        assert!(matches!(
+            camera.get_control_value(ControlType::CURRENT_TEMPERATURE),
+            Err(CameraError::Unsupported(_))
+        ));
+        assert!(matches!(
+            camera.set_control_value(ControlType::COOLER_ENABLE, 1, 0),
+            Err(CameraError::Unsupported(_))
+        ));
     }
 }
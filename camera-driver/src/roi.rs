@@ -0,0 +1,157 @@
+use crate::interface::{CameraError, CameraInfo, CameraResult, ImgType, ROIFormat};
+
+// The SDKs require the ROI width to be a multiple of 8 and the height a multiple of 2.
+pub const ROI_WIDTH_ALIGN: u32 = 8;
+pub const ROI_HEIGHT_ALIGN: u32 = 2;
+
+/// Check a requested ROI against the sensor geometry in `info`.
+///
+/// Unsupported bins or image types and empty ROIs are rejected. Otherwise the
+/// size is snapped down to the SDK alignment and to the binned sensor size, and
+/// the start position is moved so the ROI fits on the sensor. The returned ROI
+/// is the one the camera will actually use.
+pub fn validate_roi(info: &CameraInfo, req: &ROIFormat) -> CameraResult<ROIFormat> {
+    if req.bin == 0 || !info.supported_bins.contains(&req.bin) {
+        return Err(CameraError::InvalidArgument(format!(
+            "bin {} is not supported, expected one of {:?}",
+            req.bin, info.supported_bins
+        )));
+    }
+    if !info
+        .supported_img_type
+        .iter()
+        .any(|&t| t as u8 == req.img_type && t != ImgType::END)
+    {
+        return Err(CameraError::InvalidArgument(format!(
+            "img_type {} is not supported, expected one of {:?}",
+            req.img_type, info.supported_img_type
+        )));
+    }
+
+    let max_width = align_down(info.max_width / req.bin as u32, ROI_WIDTH_ALIGN);
+    let max_height = align_down(info.max_height / req.bin as u32, ROI_HEIGHT_ALIGN);
+    let width = align_down(req.width.min(max_width), ROI_WIDTH_ALIGN);
+    let height = align_down(req.height.min(max_height), ROI_HEIGHT_ALIGN);
+    if width == 0 || height == 0 {
+        return Err(CameraError::InvalidArgument(format!(
+            "roi {}x{} is smaller than the minimum {}x{}",
+            req.width, req.height, ROI_WIDTH_ALIGN, ROI_HEIGHT_ALIGN
+        )));
+    }
+
+    Ok(ROIFormat {
+        startx: req.startx.min(max_width - width),
+        starty: req.starty.min(max_height - height),
+        width,
+        height,
+        bin: req.bin,
+        img_type: req.img_type,
+    })
+}
+
+fn align_down(value: u32, align: u32) -> u32 {
+    value - value % align
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> CameraInfo {
+        CameraInfo {
+            name: "Test Camera".to_string(),
+            idx: 0,
+            max_width: 1913,
+            max_height: 1305,
+            supported_img_type: vec![ImgType::RAW8, ImgType::RAW16],
+            supported_bins: vec![1, 2, 4],
+            is_coolable: false,
+        }
+    }
+
+    fn roi(startx: u32, starty: u32, width: u32, height: u32, bin: u8) -> ROIFormat {
+        ROIFormat {
+            startx,
+            starty,
+            width,
+            height,
+            bin,
+            img_type: ImgType::RAW8 as u8,
+        }
+    }
+
+    #[test]
+    fn keeps_an_aligned_roi() {
+        let req = roi(16, 10, 640, 480, 1);
+        assert_eq!(validate_roi(&info(), &req).unwrap(), req);
+    }
+
+    #[test]
+    fn snaps_the_size_down_to_the_alignment() {
+        let res = validate_roi(&info(), &roi(0, 0, 645, 481, 1)).unwrap();
+        assert_eq!((res.width, res.height), (640, 480));
+    }
+
+    #[test]
+    fn clamps_the_size_to_the_aligned_sensor() {
+        let res = validate_roi(&info(), &roi(0, 0, 4000, 4000, 1)).unwrap();
+        assert_eq!((res.width, res.height), (1912, 1304));
+    }
+
+    #[test]
+    fn applies_the_bin_to_the_sensor_size() {
+        // 1913 / 2 = 956 -> 952, 1305 / 2 = 652.
+        let res = validate_roi(&info(), &roi(0, 0, 4000, 4000, 2)).unwrap();
+        assert_eq!((res.width, res.height, res.bin), (952, 652, 2));
+        // 1913 / 4 = 478 -> 472, 1305 / 4 = 326.
+        let res = validate_roi(&info(), &roi(0, 0, 4000, 4000, 4)).unwrap();
+        assert_eq!((res.width, res.height, res.bin), (472, 326, 4));
+    }
+
+    #[test]
+    fn moves_the_start_so_the_roi_fits() {
+        let res = validate_roi(&info(), &roi(1800, 1300, 640, 480, 1)).unwrap();
+        assert_eq!((res.startx, res.starty), (1912 - 640, 1304 - 480));
+        assert_eq!((res.width, res.height), (640, 480));
+        let res = validate_roi(&info(), &roi(900, 600, 640, 480, 2)).unwrap();
+        assert_eq!((res.startx, res.starty), (952 - 640, 652 - 480));
+    }
+
+    #[test]
+    fn rejects_unsupported_bins() {
+        for bin in [0, 3, 8] {
+            assert!(matches!(
+                validate_roi(&info(), &roi(0, 0, 640, 480, bin)),
+                Err(CameraError::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_image_types() {
+        for img_type in [ImgType::END as u8, ImgType::RGB24 as u8] {
+            let req = ROIFormat {
+                img_type,
+                ..roi(0, 0, 640, 480, 1)
+            };
+            assert!(matches!(
+                validate_roi(&info(), &req),
+                Err(CameraError::InvalidArgument(_))
+            ));
+        }
+        // END is rejected even when listed.
+        let mut info = info();
+        info.supported_img_type.push(ImgType::END);
+        let req = ROIFormat {
+            img_type: ImgType::END as u8,
+            ..roi(0, 0, 640, 480, 1)
+        };
+        assert!(validate_roi(&info, &req).is_err());
+    }
+
+    #[test]
+    fn rejects_a_roi_smaller_than_the_alignment() {
+        assert!(validate_roi(&info(), &roi(0, 0, 7, 480, 1)).is_err());
+        assert!(validate_roi(&info(), &roi(0, 0, 640, 1, 1)).is_err());
+    }
+}
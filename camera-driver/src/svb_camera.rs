@@ -1,10 +1,12 @@
-use crate::interface::{CameraInfo, CameraInterface, ControlType, ImgType, ROIFormat};
+use crate::backend::CameraBackend;
+use crate::interface::{
+    CameraError, CameraInfo, CameraInterface, CameraResult, ControlCaps, ControlType,
+    DeviceDescriptor, Frame, ImgType, ROIFormat,
+};
+use crate::roi::validate_roi;
+use crate::svbony::{camera as svb, libsvb};
 
-use base64::encode;
-use log::error;
-use serde::{Deserialize, Serialize};
-use std::sync;
-use svbony_camera_rs::{camera as svb, libsvb};
+use log::{error, warn};
 
 #[derive(Debug, Clone)]
 pub struct SVBCameraWrapper {
@@ -14,17 +16,69 @@ pub struct SVBCameraWrapper {
     is_capture: bool,
 }
 
-impl CameraInterface for SVBCameraWrapper {
-    fn num_devices() -> usize {
+impl SVBCameraWrapper {
+    // Translate an SDK error code into a CameraError for the camera at `idx`.
+    fn sdk_error(idx: usize, code: libsvb::SVB_ERROR_CODE) -> CameraError {
+        match code {
+            libsvb::SVB_ERROR_CODE_SVB_ERROR_TIMEOUT => CameraError::Timeout,
+            libsvb::SVB_ERROR_CODE_SVB_ERROR_INVALID_INDEX
+            | libsvb::SVB_ERROR_CODE_SVB_ERROR_INVALID_ID
+            | libsvb::SVB_ERROR_CODE_SVB_ERROR_CAMERA_REMOVED => CameraError::DeviceNotFound(idx),
+            // SVB_ERROR_CODE is unsigned or signed depending on the compiler bindgen follows.
+            #[allow(clippy::unnecessary_cast)]
+            code => CameraError::Sdk(code as i32),
+        }
+    }
+    // SDK strings are fixed size, nul terminated arrays.
+    fn sdk_string(raw: &[std::os::raw::c_char]) -> String {
+        let bytes: Vec<u8> = raw
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as u8)
+            .collect();
+        String::from_utf8_lossy(&bytes).trim().to_string()
+    }
+    fn map_err(&self, code: libsvb::SVB_ERROR_CODE) -> CameraError {
+        error!("svbony camera {} error: {:?}", self.info.idx, code);
+        Self::sdk_error(self.info.idx as usize, code)
+    }
+
+    pub fn num_devices() -> usize {
         svb::get_num_of_camera() as usize
     }
-    fn new(idx: usize) -> Self {
+    pub fn list_devices() -> CameraResult<Vec<DeviceDescriptor>> {
+        let mut devices = Vec::new();
+        for idx in 0..Self::num_devices() {
+            let mut info: libsvb::SVB_CAMERA_INFO = unsafe { std::mem::zeroed() };
+            let code = unsafe { libsvb::SVBGetCameraInfo(&mut info, idx as i32) };
+            if code != libsvb::SVB_ERROR_CODE_SVB_SUCCESS {
+                return Err(Self::sdk_error(idx, code));
+            }
+            // Fall back to the session camera id when the serial number is not readable.
+            let mut serial = Self::sdk_string(&info.CameraSN);
+            if serial.is_empty() {
+                serial = format!("ID{}", info.CameraID);
+            }
+            devices.push(DeviceDescriptor {
+                idx,
+                name: Self::sdk_string(&info.FriendlyName),
+                serial,
+            });
+        }
+        Ok(devices)
+    }
+    pub fn new(idx: usize) -> CameraResult<Self> {
+        if idx >= Self::num_devices() {
+            return Err(CameraError::DeviceNotFound(idx));
+        }
         let mut camera = svb::Camera::new(idx as i32);
 
-        camera.init();
+        camera.init().map_err(|e| Self::sdk_error(idx, e))?;
 
         let roi = camera.roi;
-        let img_type = camera.get_img_type().unwrap();
+        let img_type = camera
+            .get_img_type()
+            .map_err(|e| Self::sdk_error(idx, e))?;
 
         let roi = ROIFormat {
             startx: roi.startx as u32,
@@ -38,7 +92,7 @@ impl CameraInterface for SVBCameraWrapper {
         let info = camera.info;
         let props = camera.prop;
 
-        let mut name: Vec<u8> = info.FriendlyName.iter().map(|&x| x as u8).collect();
+        let name: Vec<u8> = info.FriendlyName.iter().map(|&x| x as u8).collect();
         let info = CameraInfo {
             name: String::from_utf8_lossy(&name).replace("\\u0000", ""),
             idx: idx as u32,
@@ -48,66 +102,156 @@ impl CameraInterface for SVBCameraWrapper {
                 .SupportedVideoFormat
                 .iter()
                 .take_while(|&x| *x != -1)
-                .map(|x| ImgType::from_i32(x))
+                .map(ImgType::from_i32)
+                .collect(),
+            supported_bins: props
+                .SupportedBins
+                .iter()
+                .take_while(|&x| *x != 0)
+                .map(|x| *x as u8)
                 .collect(),
-            supported_bins: props.SupportedBins.iter().map(|x| *x as u8).collect(),
             is_coolable: false,
         };
 
-        camera.adjust_white_balance();
-        camera.set_ctl_value(libsvb::SVB_CONTROL_TYPE_SVB_FLIP, 3, 0);
+        // Best effort, a camera without these controls is still usable.
+        if let Err(e) = camera.adjust_white_balance() {
+            warn!("svbony camera {} white balance error: {:?}", idx, e);
+        }
+        if let Err(e) = camera.set_ctl_value(libsvb::SVB_CONTROL_TYPE_SVB_FLIP, 3, 0) {
+            warn!("svbony camera {} FLIP error: {:?}", idx, e);
+        }
 
-        SVBCameraWrapper {
+        Ok(SVBCameraWrapper {
             camera,
             roi,
             info,
             is_capture: false,
-        }
+        })
     }
+}
+
+/// Backend of the SVBONY cameras, through the vendor SDK.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SvbBackend;
 
-    fn start_capture(&mut self) {
-        self.camera.start_video_capture();
-        self.is_capture = true
+impl CameraBackend for SvbBackend {
+    fn name(&self) -> &'static str {
+        "svbony"
     }
-    fn stop_capture(&mut self) {
-        self.camera.stop_video_capture();
-        self.is_capture = false
+    fn enumerate(&self) -> CameraResult<Vec<DeviceDescriptor>> {
+        SVBCameraWrapper::list_devices()
     }
-    fn get_frame(&self) -> String {
-        let buf = self.camera.get_video_frame().unwrap();
+    fn open(&self, device: &DeviceDescriptor) -> CameraResult<Box<dyn CameraInterface + Send>> {
+        Ok(Box::new(SVBCameraWrapper::new(device.idx)?))
+    }
+}
 
-        encode(buf)
+impl CameraInterface for SVBCameraWrapper {
+
+    fn start_capture(&mut self) -> CameraResult<()> {
+        self.camera
+            .start_video_capture()
+            .map_err(|e| self.map_err(e))?;
+        self.is_capture = true;
+        Ok(())
     }
-    fn close(&self) {
-        self.camera.close();
+    fn stop_capture(&mut self) -> CameraResult<()> {
+        self.is_capture = false;
+        self.camera
+            .stop_video_capture()
+            .map_err(|e| self.map_err(e))
+    }
+    fn get_frame(&self) -> CameraResult<Frame> {
+        let buf = self
+            .camera
+            .get_video_frame()
+            .map_err(|e| self.map_err(e))?;
+        let img_type = self.get_img_type()?;
+        Ok(Frame::new(buf, self.roi.width, self.roi.height, img_type))
+    }
+    fn close(&self) -> CameraResult<()> {
+        self.camera.close().map_err(|e| self.map_err(e))
     }
     fn get_info(&self) -> CameraInfo {
         self.info.clone()
     }
+    fn get_control_caps(&self) -> CameraResult<Vec<ControlCaps>> {
+        let camera_id = self.camera.info.CameraID;
+        let mut num_ctrl = 0;
+        let code = unsafe { libsvb::SVBGetNumOfControls(camera_id, &mut num_ctrl) };
+        if code != libsvb::SVB_ERROR_CODE_SVB_SUCCESS {
+            return Err(self.map_err(code));
+        }
+
+        let mut caps = Vec::new();
+        for i in 0..num_ctrl {
+            let mut svb_caps: libsvb::SVB_CONTROL_CAPS = unsafe { std::mem::zeroed() };
+            let code = unsafe { libsvb::SVBGetControlCaps(camera_id, i, &mut svb_caps) };
+            if code != libsvb::SVB_ERROR_CODE_SVB_SUCCESS {
+                return Err(self.map_err(code));
+            }
+            // Skip controls the interface has no ControlType for.
+            let control_type = match ControlType::from_svb(svb_caps.ControlType) {
+                Some(control_type) => control_type,
+                None => continue,
+            };
+            let name: Vec<u8> = svb_caps
+                .Name
+                .iter()
+                .take_while(|&&x| x != 0)
+                .map(|&x| x as u8)
+                .collect();
+            caps.push(ControlCaps {
+                name: String::from_utf8_lossy(&name).to_string(),
+                max_value: svb_caps.MaxValue as i64,
+                min_value: svb_caps.MinValue as i64,
+                default_value: svb_caps.DefaultValue as i64,
+                is_auto_supported: svb_caps.IsAutoSupported == libsvb::SVB_BOOL_SVB_TRUE,
+                is_writable: svb_caps.IsWritable == libsvb::SVB_BOOL_SVB_TRUE,
+                control_type,
+            });
+        }
+        Ok(caps)
+    }
     fn get_roi(&self) -> ROIFormat {
-        self.roi.clone()
+        self.roi
     }
 
-    fn set_control_value(&self, ctrl_type: ControlType, value: i64, is_auto: i64) {
+    fn set_control_value(
+        &self,
+        ctrl_type: ControlType,
+        value: i64,
+        is_auto: i64,
+    ) -> CameraResult<()> {
         let svb_ctrl_type = ControlType::to_svb(ctrl_type);
         self.camera
-            .set_ctl_value(svb_ctrl_type, value, is_auto as u32);
+            .set_ctl_value(svb_ctrl_type, value, is_auto as libsvb::SVB_BOOL)
+            .map_err(|e| self.map_err(e))
     }
-    fn get_control_value(&self, ctrl_type: ControlType) -> i64 {
+    fn get_control_value(&self, ctrl_type: ControlType) -> CameraResult<i64> {
         let svb_ctrl_type = ControlType::to_svb(ctrl_type);
-        match self.camera.get_ctl_value(svb_ctrl_type) {
-            Ok(state) => state.value,
-            Err(e) => {
-                error!("get_control_value error: {:?}", e);
-                -1
-            }
-        }
+        self.camera
+            .get_ctl_value(svb_ctrl_type)
+            .map(|state| state.value)
+            .map_err(|e| self.map_err(e))
+    }
+    // The image type is cached in the ROI by open, set_roi and set_img_type.
+    fn get_img_type(&self) -> CameraResult<ImgType> {
+        Ok(ImgType::from_i32(&(self.roi.img_type as i32)))
     }
-    fn get_img_type(&self) -> ImgType {
-        let svb_img_t = self.camera.get_img_type().unwrap();
-        ImgType::from_i32(&svb_img_t)
+    fn set_img_type(&mut self, img_type: ImgType) -> CameraResult<()> {
+        if !self.info.supported_img_type.contains(&img_type) {
+            return Err(CameraError::InvalidArgument(format!(
+                "img_type {:?} is not supported, expected one of {:?}",
+                img_type, self.info.supported_img_type
+            )));
+        }
+        self.camera
+            .set_img_type(ImgType::to_svb(img_type))
+            .map_err(|e| self.map_err(e))?;
+        self.roi.img_type = img_type as u8;
+        Ok(())
     }
-    fn set_img_type(&mut self, img_type: ImgType) {}
     fn is_capture(&self) -> bool {
         self.is_capture
     }
@@ -122,16 +266,29 @@ impl CameraInterface for SVBCameraWrapper {
         height: u32,
         bin: u8,
         img_type: ImgType,
-    ) {
+    ) -> CameraResult<()> {
+        let req = ROIFormat {
+            startx,
+            starty,
+            width,
+            height,
+            bin,
+            img_type: img_type as u8,
+        };
+        let roi = validate_roi(&self.info, &req)?;
         let svb_img_type = ImgType::to_svb(img_type);
-        self.camera.set_roi_format(
-            startx as i32,
-            starty as i32,
-            width as i32,
-            height as i32,
-            bin as i32,
-        );
-        self.camera.set_img_type(svb_img_type);
+        self.camera
+            .set_roi_format(
+                roi.startx as i32,
+                roi.starty as i32,
+                roi.width as i32,
+                roi.height as i32,
+                roi.bin as i32,
+            )
+            .map_err(|e| self.map_err(e))?;
+        self.camera
+            .set_img_type(svb_img_type)
+            .map_err(|e| self.map_err(e))?;
         let roi = self.camera.roi;
         self.roi = ROIFormat {
             startx: roi.startx as u32,
@@ -140,9 +297,12 @@ impl CameraInterface for SVBCameraWrapper {
             height: roi.height as u32,
             bin: roi.bin as u8,
             img_type: img_type as u8,
-        }
+        };
+        Ok(())
     }
-    fn adjust_white_balance(&self) {
-        self.camera.adjust_white_balance();
+    fn adjust_white_balance(&self) -> CameraResult<()> {
+        self.camera
+            .adjust_white_balance()
+            .map_err(|e| self.map_err(e))
     }
 }
@@ -0,0 +1,182 @@
+//! An SVBONY camera opened through the SDK.
+use super::libsvb::*;
+use std::os::raw::{c_int, c_long};
+
+// Extra time given to SVBGetVideoData on top of twice the exposure.
+const FRAME_TIMEOUT_MARGIN_MS: i64 = 500;
+
+/// Sensor area read out, in binned pixels.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ROIFormat {
+    pub startx: i32,
+    pub starty: i32,
+    pub width: i32,
+    pub height: i32,
+    pub bin: i32,
+}
+
+/// Value of a control and whether it is in auto mode.
+#[derive(Debug, Clone, Copy)]
+pub struct ControlState {
+    pub value: i64,
+    pub is_auto: SVB_BOOL,
+}
+
+/// Number of connected cameras.
+pub fn get_num_of_camera() -> i32 {
+    unsafe { SVBGetNumOfConnectedCameras() }
+}
+
+fn check(code: SVB_ERROR_CODE) -> Result<(), SVB_ERROR_CODE> {
+    match code {
+        SVB_ERROR_CODE_SVB_SUCCESS => Ok(()),
+        code => Err(code),
+    }
+}
+
+fn bytes_per_pixel(img_type: SVB_IMG_TYPE) -> usize {
+    match img_type {
+        SVB_IMG_TYPE_SVB_IMG_RAW8 | SVB_IMG_TYPE_SVB_IMG_Y8 => 1,
+        SVB_IMG_TYPE_SVB_IMG_RGB24 => 3,
+        SVB_IMG_TYPE_SVB_IMG_RGB32 => 4,
+        _ => 2,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Camera {
+    /// Camera id used by the SDK calls, valid while the camera is connected.
+    pub id: i32,
+    /// Index in the enumeration the camera was created from.
+    pub idx: i32,
+    pub info: SVB_CAMERA_INFO,
+    /// Read by `init`.
+    pub prop: SVB_CAMERA_PROPERTY,
+    pub roi: ROIFormat,
+    img_type: SVB_IMG_TYPE,
+}
+
+impl Camera {
+    /// Camera at `idx` in the enumeration, not opened yet.
+    pub fn new(idx: i32) -> Self {
+        let mut info: SVB_CAMERA_INFO = unsafe { std::mem::zeroed() };
+        // A failure leaves the id at -1, init then reports the error.
+        let id = match check(unsafe { SVBGetCameraInfo(&mut info, idx) }) {
+            Ok(()) => info.CameraID,
+            Err(_) => -1,
+        };
+        Camera {
+            id,
+            idx,
+            info,
+            prop: unsafe { std::mem::zeroed() },
+            roi: ROIFormat::default(),
+            img_type: SVB_IMG_TYPE_SVB_IMG_RAW8,
+        }
+    }
+
+    /// Opens the camera in video mode and reads its properties, ROI and image type.
+    pub fn init(&mut self) -> Result<(), SVB_ERROR_CODE> {
+        if self.id < 0 {
+            return Err(SVB_ERROR_CODE_SVB_ERROR_INVALID_INDEX);
+        }
+        check(unsafe { SVBOpenCamera(self.id) })?;
+        check(unsafe { SVBGetCameraProperty(self.id, &mut self.prop) })?;
+        check(unsafe { SVBSetCameraMode(self.id, SVB_CAMERA_MODE_SVB_MODE_NORMAL) })?;
+        self.read_roi_format()?;
+        self.img_type = self.get_img_type()?;
+        Ok(())
+    }
+
+    pub fn close(&self) -> Result<(), SVB_ERROR_CODE> {
+        check(unsafe { SVBCloseCamera(self.id) })
+    }
+
+    pub fn get_img_type(&self) -> Result<SVB_IMG_TYPE, SVB_ERROR_CODE> {
+        let mut img_type = SVB_IMG_TYPE_SVB_IMG_END;
+        check(unsafe { SVBGetOutputImageType(self.id, &mut img_type) })?;
+        Ok(img_type)
+    }
+
+    pub fn set_img_type(&mut self, img_type: SVB_IMG_TYPE) -> Result<(), SVB_ERROR_CODE> {
+        check(unsafe { SVBSetOutputImageType(self.id, img_type) })?;
+        self.img_type = img_type;
+        Ok(())
+    }
+
+    pub fn get_ctl_value(&self, ctrl_type: SVB_CONTROL_TYPE) -> Result<ControlState, SVB_ERROR_CODE> {
+        let mut value: c_long = 0;
+        let mut is_auto = SVB_BOOL_SVB_FALSE;
+        check(unsafe { SVBGetControlValue(self.id, ctrl_type, &mut value, &mut is_auto) })?;
+        Ok(ControlState {
+            value: value as i64,
+            is_auto,
+        })
+    }
+
+    pub fn set_ctl_value(
+        &self,
+        ctrl_type: SVB_CONTROL_TYPE,
+        value: i64,
+        is_auto: SVB_BOOL,
+    ) -> Result<(), SVB_ERROR_CODE> {
+        check(unsafe { SVBSetControlValue(self.id, ctrl_type, value as c_long, is_auto) })
+    }
+
+    pub fn adjust_white_balance(&self) -> Result<(), SVB_ERROR_CODE> {
+        check(unsafe { SVBWhiteBalanceOnce(self.id) })
+    }
+
+    /// Sets the ROI, then reads back the one applied by the camera.
+    pub fn set_roi_format(
+        &mut self,
+        startx: i32,
+        starty: i32,
+        width: i32,
+        height: i32,
+        bin: i32,
+    ) -> Result<(), SVB_ERROR_CODE> {
+        check(unsafe { SVBSetROIFormat(self.id, startx, starty, width, height, bin) })?;
+        self.read_roi_format()
+    }
+
+    fn read_roi_format(&mut self) -> Result<(), SVB_ERROR_CODE> {
+        let mut roi = ROIFormat::default();
+        check(unsafe {
+            SVBGetROIFormat(
+                self.id,
+                &mut roi.startx as *mut c_int,
+                &mut roi.starty as *mut c_int,
+                &mut roi.width as *mut c_int,
+                &mut roi.height as *mut c_int,
+                &mut roi.bin as *mut c_int,
+            )
+        })?;
+        self.roi = roi;
+        Ok(())
+    }
+
+    pub fn start_video_capture(&self) -> Result<(), SVB_ERROR_CODE> {
+        check(unsafe { SVBStartVideoCapture(self.id) })
+    }
+
+    pub fn stop_video_capture(&self) -> Result<(), SVB_ERROR_CODE> {
+        check(unsafe { SVBStopVideoCapture(self.id) })
+    }
+
+    /// Waits for the next frame of the video capture, in the current ROI and image type.
+    pub fn get_video_frame(&self) -> Result<Vec<u8>, SVB_ERROR_CODE> {
+        let len = self.roi.width.max(0) as usize
+            * self.roi.height.max(0) as usize
+            * bytes_per_pixel(self.img_type);
+        let mut buf = vec![0u8; len];
+        let exposure_ms = self
+            .get_ctl_value(SVB_CONTROL_TYPE_SVB_EXPOSURE)
+            .map_or(0, |state| state.value / 1000);
+        let wait_ms = (exposure_ms * 2 + FRAME_TIMEOUT_MARGIN_MS).min(c_int::MAX as i64);
+        check(unsafe {
+            SVBGetVideoData(self.id, buf.as_mut_ptr(), len as c_long, wait_ms as c_int)
+        })?;
+        Ok(buf)
+    }
+}
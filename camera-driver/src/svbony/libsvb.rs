@@ -0,0 +1,4 @@
+//! Declarations of SVBCameraSDK.h, generated by bindgen in build.rs.
+#![allow(non_upper_case_globals, non_camel_case_types, non_snake_case, dead_code)]
+
+include!(concat!(env!("OUT_DIR"), "/libsvb.rs"));
@@ -0,0 +1,5 @@
+//! Rust bindings to the SVBONY camera SDK.
+//!
+//! `libsvb` declares the C API of SVBCameraSDK.h, `camera` wraps an opened camera.
+pub mod camera;
+pub mod libsvb;
@@ -0,0 +1 @@
+#include <SVBCameraSDK.h>
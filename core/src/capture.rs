@@ -0,0 +1,119 @@
+///
+/// Capture worker : owns the frame loop of one camera.
+///
+/// The loop runs on a blocking thread so the blocking SDK call `get_frame` never
+/// stalls the tokio runtime. The camera mutex is only held while a frame is read,
+/// so control commands are served between two frames. Frames are handed to the
+/// publisher through a bounded FrameQueue.
+///
+use crate::frame::{CapturedFrame, FrameMetadata};
+use crate::queue::{FrameQueue, PushOutcome};
+use crate::status::CameraStats;
+use camera_driver::interface::{CameraError, CameraInterface};
+use log::{error, info};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::sync::Mutex as StdMutex;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+#[derive(Debug)]
+pub enum CaptureEvent {
+    Frame(CapturedFrame),
+    // The loop stopped because the camera failed.
+    Error(CameraError),
+}
+
+#[derive(Debug)]
+pub struct CaptureWorker {
+    running: Arc<AtomicBool>,
+    queue: Arc<FrameQueue<CaptureEvent>>,
+    handle: JoinHandle<()>,
+}
+
+impl CaptureWorker {
+    pub fn spawn<T: CameraInterface + Send + 'static>(
+        camera: Arc<Mutex<T>>,
+        camera_id: String,
+        stats: Arc<StdMutex<CameraStats>>,
+        queue: Arc<FrameQueue<CaptureEvent>>,
+    ) -> Self {
+        let running = Arc::new(AtomicBool::new(true));
+        let flag = running.clone();
+        let tx = queue.clone();
+        let handle = tokio::task::spawn_blocking(move || {
+            info!("[ CaptureWorker ] : capture loop started");
+            let mut seq: u64 = 0;
+            while flag.load(Ordering::Acquire) {
+                // The metadata is read under the same lock so it always describes the frame.
+                let res = {
+                    let camera = camera.blocking_lock();
+                    camera.get_frame().map(|frame| CapturedFrame {
+                        meta: FrameMetadata::collect(&*camera, &camera_id, seq),
+                        frame,
+                    })
+                };
+                match res {
+                    Ok(frame) => {
+                        stats.lock().unwrap().record_frame();
+                        seq += 1;
+                        match tx.push(CaptureEvent::Frame(frame)) {
+                            PushOutcome::Queued => {}
+                            PushOutcome::DroppedOldest | PushOutcome::DroppedNewest => {
+                                stats.lock().unwrap().record_drop();
+                            }
+                            PushOutcome::Closed => break,
+                        }
+                    }
+                    Err(CameraError::Timeout) => {
+                        stats.lock().unwrap().record_failure();
+                    }
+                    Err(e) => {
+                        // The device is gone or broken; stop the loop instead of spinning on errors.
+                        error!("[ CaptureWorker ] : capture loop aborted : {}", e);
+                        {
+                            let mut stats = stats.lock().unwrap();
+                            stats.record_failure();
+                            stats.record_error(&e);
+                        }
+                        // Leave the camera out of video mode, as StopCapture would.
+                        {
+                            let mut camera = camera.blocking_lock();
+                            camera.set_is_capture(false);
+                            if let Err(e) = camera.stop_capture() {
+                                error!("[ CaptureWorker ] : failed to stop capturing : {}", e);
+                            }
+                        }
+                        // Stopped before the error is handed over, the publisher then
+                        // republishes the inventory with the camera idle.
+                        flag.store(false, Ordering::Release);
+                        tx.finish(CaptureEvent::Error(e));
+                        break;
+                    }
+                }
+            }
+            tx.close();
+            flag.store(false, Ordering::Release);
+            info!("[ CaptureWorker ] : capture loop stopped");
+        });
+        Self {
+            running,
+            queue,
+            handle,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Acquire)
+    }
+
+    // Asks the loop to stop and waits until the frame in flight is finished.
+    // Closing the queue also releases a worker blocked by DropPolicy::Block.
+    pub async fn stop(self) {
+        self.running.store(false, Ordering::Release);
+        self.queue.close();
+        if let Err(e) = self.handle.await {
+            error!("[ CaptureWorker ] : capture loop panicked : {:?}", e);
+        }
+    }
+}
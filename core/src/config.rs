@@ -0,0 +1,735 @@
+///
+/// Server configuration.
+///
+/// Settings are read from a TOML file (`--config`, default `mqtt-camera-server.toml`
+/// in the working directory when it exists), then overridden by command line options.
+/// `--mock-cameras` can also be given by the MOCK_CAMERAS environment variable.
+/// Every field is optional, missing ones keep the default shown below.
+///
+/// [broker]
+/// host = "localhost"
+/// port = 1883
+/// client_id = "mqtt-server"
+/// # username = "camera"
+/// # password = "secret"
+/// keep_alive_secs = 20
+/// protocol = "v4"                 # "v4" (MQTT 3.1.1) or "v5"
+/// max_packet_size = 67108864      # bytes, must hold a full frame
+/// reconnect_min_secs = 1          # delay before reconnecting, doubled after
+/// reconnect_max_secs = 30         # each failed attempt up to reconnect_max_secs
+///
+/// [broker.tls]                    # connect with TLS when this section is present
+/// ca_file = "ca.pem"              # CA certificate(s) of the broker, PEM
+/// # client_cert_file = "client.pem"   # client certificate authentication, PEM,
+/// # client_key_file = "client.key"    # both files are required
+/// # client_key_type = "rsa"       # "rsa" or "ecc"
+///
+/// [qos]                           # 0, 1 or 2
+/// command = 2
+/// response = 1
+/// frame = 1
+/// status = 1
+///
+/// [topics]
+/// prefix = "camera"
+/// legacy = true                   # also serve camera/instr, camera/responce, camera/frame
+///
+/// [cameras]
+/// backends = ["mock", "svbony"]   # all built backends by default, "svbony" needs
+///                                 # the svbony cargo feature
+///
+/// [cameras.aliases]               # camera id used in the topics and the commands,
+/// # "svbony-1234567" = "guider"   # by default "<vendor>-<serial>"
+///
+/// [[cameras.mock]]                # one section per simulated camera, none by default
+/// name = "Mock Camera"
+/// # serial = "MOCK0000"           # "MOCK<index>" by default
+/// max_width = 1912
+/// max_height = 1304
+/// supported_img_type = ["RAW8", "RAW16"]
+/// supported_bins = [1, 2, 4, 8]
+/// is_coolable = false             # adds the cooler and temperature controls
+///
+/// [capture]                       # defaults of StartCapture
+/// drop_policy = "drop_oldest"
+/// queue_capacity = 8
+/// max_queue_capacity = 256        # largest queue_capacity a StartCapture may ask for
+/// encoding = "binary"
+///
+use crate::frame::FrameEncoding;
+use crate::queue::{DropPolicy, DEFAULT_MAX_QUEUE_CAPACITY, DEFAULT_QUEUE_CAPACITY};
+use crate::transport::{ClientKeyType, ProtocolVersion};
+use camera_driver::backend::BackendRegistry;
+use camera_driver::mock::MockCameraSpec;
+use clap::Parser;
+use rumqttc::QoS;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const DEFAULT_CONFIG_FILE: &str = "mqtt-camera-server.toml";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "cannot read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "invalid config {}: {}", path.display(), e),
+            ConfigError::Invalid(msg) => write!(f, "invalid config: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BrokerConfig {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub keep_alive_secs: u64,
+    pub protocol: ProtocolVersion,
+    pub max_packet_size: u32,
+    pub reconnect_min_secs: u64,
+    pub reconnect_max_secs: u64,
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub ca_file: PathBuf,
+    pub client_cert_file: Option<PathBuf>,
+    pub client_key_file: Option<PathBuf>,
+    #[serde(default)]
+    pub client_key_type: ClientKeyType,
+}
+
+impl Default for BrokerConfig {
+    fn default() -> Self {
+        Self {
+            host: "localhost".to_string(),
+            port: 1883,
+            client_id: "mqtt-server".to_string(),
+            username: None,
+            password: None,
+            keep_alive_secs: 20,
+            protocol: ProtocolVersion::V4,
+            max_packet_size: 64 * 1024 * 1024,
+            reconnect_min_secs: 1,
+            reconnect_max_secs: 30,
+            tls: None,
+        }
+    }
+}
+
+// QoS of each class of topics, as 0, 1 or 2.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QosConfig {
+    pub command: u8,
+    pub response: u8,
+    pub frame: u8,
+    pub status: u8,
+}
+
+impl Default for QosConfig {
+    fn default() -> Self {
+        Self {
+            command: 2,
+            response: 1,
+            frame: 1,
+            status: 1,
+        }
+    }
+}
+
+impl QosConfig {
+    pub fn command(&self) -> QoS {
+        to_qos(self.command)
+    }
+    pub fn response(&self) -> QoS {
+        to_qos(self.response)
+    }
+    pub fn frame(&self) -> QoS {
+        to_qos(self.frame)
+    }
+    pub fn status(&self) -> QoS {
+        to_qos(self.status)
+    }
+}
+
+// Values are checked by Config::validate.
+fn to_qos(qos: u8) -> QoS {
+    rumqttc::qos(qos).unwrap_or(QoS::AtLeastOnce)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TopicsConfig {
+    pub prefix: String,
+    pub legacy: bool,
+}
+
+impl Default for TopicsConfig {
+    fn default() -> Self {
+        Self {
+            prefix: "camera".to_string(),
+            legacy: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CamerasConfig {
+    pub backends: Vec<String>,
+    // default camera id -> alias
+    pub aliases: BTreeMap<String, String>,
+    // cameras of the mock backend
+    pub mock: Vec<MockCameraSpec>,
+}
+
+impl Default for CamerasConfig {
+    fn default() -> Self {
+        Self {
+            backends: BackendRegistry::builtin()
+                .names()
+                .iter()
+                .map(|b| b.to_string())
+                .collect(),
+            aliases: BTreeMap::new(),
+            mock: Vec::new(),
+        }
+    }
+}
+
+impl CamerasConfig {
+    pub fn is_enabled(&self, backend: &str) -> bool {
+        self.backends.iter().any(|b| b == backend)
+    }
+
+    // Stable id of a camera : its alias, or "<vendor>-<serial>" with the characters
+    // that cannot appear in a topic level replaced by '_'.
+    pub fn camera_id(&self, vendor: &str, serial: &str) -> String {
+        let id: String = format!("{}-{}", vendor, serial)
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        self.aliases.get(&id).cloned().unwrap_or(id)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureConfig {
+    pub drop_policy: DropPolicy,
+    pub queue_capacity: usize,
+    pub max_queue_capacity: usize,
+    pub encoding: FrameEncoding,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            drop_policy: DropPolicy::default(),
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            max_queue_capacity: DEFAULT_MAX_QUEUE_CAPACITY,
+            encoding: FrameEncoding::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub broker: BrokerConfig,
+    pub qos: QosConfig,
+    pub topics: TopicsConfig,
+    pub cameras: CamerasConfig,
+    pub capture: CaptureConfig,
+}
+
+// Command line options, they take precedence over the config file.
+#[derive(Debug, Parser)]
+#[command(name = "core", about = "MQTT camera server")]
+pub struct Cli {
+    /// Path of the TOML config file
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+    /// Broker host name
+    #[arg(long)]
+    pub host: Option<String>,
+    /// Broker port
+    #[arg(long)]
+    pub port: Option<u16>,
+    /// MQTT client id
+    #[arg(long)]
+    pub client_id: Option<String>,
+    /// Broker user name
+    #[arg(long)]
+    pub username: Option<String>,
+    /// Broker password
+    #[arg(long)]
+    pub password: Option<String>,
+    /// Keep alive interval in seconds
+    #[arg(long)]
+    pub keep_alive: Option<u64>,
+    /// MQTT protocol version : v4 or v5
+    #[arg(long)]
+    pub protocol: Option<ProtocolVersion>,
+    /// CA certificate of the broker (PEM), enables TLS
+    #[arg(long)]
+    pub tls_ca: Option<PathBuf>,
+    /// Client certificate (PEM) for TLS client authentication
+    #[arg(long, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+    /// Client private key (PEM) for TLS client authentication
+    #[arg(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+    /// Algorithm of the client private key : rsa or ecc
+    #[arg(long, requires = "tls_key")]
+    pub tls_key_type: Option<ClientKeyType>,
+    /// QoS of the command topics
+    #[arg(long)]
+    pub qos_command: Option<u8>,
+    /// QoS of the response topics
+    #[arg(long)]
+    pub qos_response: Option<u8>,
+    /// QoS of the frame topics
+    #[arg(long)]
+    pub qos_frame: Option<u8>,
+    /// QoS of the status topics
+    #[arg(long)]
+    pub qos_status: Option<u8>,
+    /// Prefix of the per camera topics
+    #[arg(long)]
+    pub topic_prefix: Option<String>,
+    /// Serve the legacy shared topics : true or false
+    #[arg(long)]
+    pub legacy_topics: Option<bool>,
+    /// Enabled camera backends, comma separated
+    #[arg(long, value_delimiter = ',')]
+    pub backends: Option<Vec<String>>,
+    /// Mock cameras : a number of cameras, or a TOML array of cameras like
+    /// [{ name = "Guider", max_width = 640, max_height = 480 }]
+    #[arg(long, env = "MOCK_CAMERAS")]
+    pub mock_cameras: Option<String>,
+    /// Default drop policy of StartCapture : drop_oldest, drop_newest or block
+    #[arg(long)]
+    pub drop_policy: Option<DropPolicy>,
+    /// Default frame queue capacity of StartCapture
+    #[arg(long)]
+    pub queue_capacity: Option<usize>,
+    /// Largest frame queue capacity a StartCapture may ask for
+    #[arg(long)]
+    pub max_queue_capacity: Option<usize>,
+    /// Default frame encoding of StartCapture : binary or base64
+    #[arg(long)]
+    pub encoding: Option<FrameEncoding>,
+}
+
+// A number keeps that many cameras of `current`, adding default ones when more are
+// asked, anything else is read as a TOML array of cameras.
+fn parse_mock_cameras(
+    value: &str,
+    current: &[MockCameraSpec],
+) -> Result<Vec<MockCameraSpec>, ConfigError> {
+    if let Ok(count) = value.trim().parse::<usize>() {
+        let mut cameras: Vec<MockCameraSpec> = current.iter().take(count).cloned().collect();
+        cameras.resize(count, MockCameraSpec::default());
+        return Ok(cameras);
+    }
+    #[derive(Deserialize)]
+    struct MockCameras {
+        mock: Vec<MockCameraSpec>,
+    }
+    toml::from_str::<MockCameras>(&format!("mock = {}", value))
+        .map(|cameras| cameras.mock)
+        .map_err(|e| ConfigError::Invalid(format!("invalid mock cameras {:?}: {}", value, e)))
+}
+
+impl Config {
+    // Reads the config file and applies the command line options.
+    pub fn load() -> Result<Config, ConfigError> {
+        let cli = Cli::parse();
+        let mut config = match &cli.config {
+            Some(path) => Config::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Config::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Config::default(),
+        };
+        config.apply(cli)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let raw = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+        toml::from_str(&raw).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    fn apply(&mut self, cli: Cli) -> Result<(), ConfigError> {
+        let broker = &mut self.broker;
+        if let Some(host) = cli.host {
+            broker.host = host;
+        }
+        if let Some(port) = cli.port {
+            broker.port = port;
+        }
+        if let Some(client_id) = cli.client_id {
+            broker.client_id = client_id;
+        }
+        if cli.username.is_some() {
+            broker.username = cli.username;
+        }
+        if cli.password.is_some() {
+            broker.password = cli.password;
+        }
+        if let Some(keep_alive) = cli.keep_alive {
+            broker.keep_alive_secs = keep_alive;
+        }
+        if let Some(protocol) = cli.protocol {
+            broker.protocol = protocol;
+        }
+        if let Some(ca_file) = cli.tls_ca {
+            let tls = broker.tls.get_or_insert(TlsConfig {
+                ca_file: ca_file.clone(),
+                client_cert_file: None,
+                client_key_file: None,
+                client_key_type: ClientKeyType::default(),
+            });
+            tls.ca_file = ca_file;
+        }
+        if cli.tls_cert.is_some() {
+            let tls = broker.tls.as_mut().ok_or_else(|| {
+                ConfigError::Invalid(
+                    "--tls-cert needs --tls-ca or a [broker.tls] section".to_string(),
+                )
+            })?;
+            tls.client_cert_file = cli.tls_cert;
+            tls.client_key_file = cli.tls_key;
+            if let Some(key_type) = cli.tls_key_type {
+                tls.client_key_type = key_type;
+            }
+        }
+
+        let qos = &mut self.qos;
+        qos.command = cli.qos_command.unwrap_or(qos.command);
+        qos.response = cli.qos_response.unwrap_or(qos.response);
+        qos.frame = cli.qos_frame.unwrap_or(qos.frame);
+        qos.status = cli.qos_status.unwrap_or(qos.status);
+
+        if let Some(prefix) = cli.topic_prefix {
+            self.topics.prefix = prefix;
+        }
+        if let Some(legacy) = cli.legacy_topics {
+            self.topics.legacy = legacy;
+        }
+        if let Some(backends) = cli.backends {
+            self.cameras.backends = backends;
+        }
+        if let Some(mock) = cli.mock_cameras {
+            self.cameras.mock = parse_mock_cameras(&mock, &self.cameras.mock)?;
+        }
+
+        let capture = &mut self.capture;
+        capture.drop_policy = cli.drop_policy.unwrap_or(capture.drop_policy);
+        capture.queue_capacity = cli.queue_capacity.unwrap_or(capture.queue_capacity);
+        capture.max_queue_capacity = cli.max_queue_capacity.unwrap_or(capture.max_queue_capacity);
+        capture.encoding = cli.encoding.unwrap_or(capture.encoding);
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if let Some(tls) = &self.broker.tls {
+            if tls.client_cert_file.is_some() != tls.client_key_file.is_some() {
+                return Err(ConfigError::Invalid(
+                    "broker.tls.client_cert_file and broker.tls.client_key_file must be given together"
+                        .to_string(),
+                ));
+            }
+        }
+        if self.broker.username.is_none() && self.broker.password.is_some() {
+            return Err(ConfigError::Invalid(
+                "broker.password is set without broker.username".to_string(),
+            ));
+        }
+        if self.broker.reconnect_min_secs > self.broker.reconnect_max_secs {
+            return Err(ConfigError::Invalid(
+                "broker.reconnect_min_secs must not exceed broker.reconnect_max_secs".to_string(),
+            ));
+        }
+        if self.broker.keep_alive_secs < 5 {
+            return Err(ConfigError::Invalid(
+                "broker.keep_alive_secs must be at least 5".to_string(),
+            ));
+        }
+        let qos = &self.qos;
+        for (name, value) in [
+            ("command", qos.command),
+            ("response", qos.response),
+            ("frame", qos.frame),
+            ("status", qos.status),
+        ] {
+            if value > 2 {
+                return Err(ConfigError::Invalid(format!(
+                    "qos.{} must be 0, 1 or 2, got {}",
+                    name, value
+                )));
+            }
+        }
+        if self.topics.prefix.is_empty() || self.topics.prefix.contains(['+', '#']) {
+            return Err(ConfigError::Invalid(format!(
+                "topics.prefix {:?} is not a valid topic prefix",
+                self.topics.prefix
+            )));
+        }
+        let registry = BackendRegistry::builtin();
+        for backend in &self.cameras.backends {
+            if registry.get(backend).is_none() {
+                return Err(ConfigError::Invalid(format!(
+                    "unknown camera backend {:?}, expected one of {:?}",
+                    backend,
+                    registry.names()
+                )));
+            }
+        }
+        let mut aliases: Vec<&String> = self.cameras.aliases.values().collect();
+        for alias in &aliases {
+            // Numeric ids would be mistaken for a legacy camera_idx.
+            if alias.is_empty()
+                || alias.contains(['/', '+', '#'])
+                || alias.parse::<usize>().is_ok()
+            {
+                return Err(ConfigError::Invalid(format!(
+                    "camera alias {:?} is not a valid camera id",
+                    alias
+                )));
+            }
+        }
+        aliases.sort();
+        if let Some(pair) = aliases.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(ConfigError::Invalid(format!(
+                "camera alias {:?} is given to several cameras",
+                pair[0]
+            )));
+        }
+        let mut serials = Vec::new();
+        for (idx, spec) in self.cameras.mock.iter().enumerate() {
+            if spec.max_width == 0
+                || spec.max_height == 0
+                || spec.supported_img_type.is_empty()
+                || spec.supported_bins.is_empty()
+            {
+                return Err(ConfigError::Invalid(format!(
+                    "cameras.mock[{}] needs a resolution, an image type and a bin",
+                    idx
+                )));
+            }
+            let serial = spec.serial(idx);
+            if serials.contains(&serial) {
+                return Err(ConfigError::Invalid(format!(
+                    "mock camera serial {:?} is given to several cameras",
+                    serial
+                )));
+            }
+            serials.push(serial);
+        }
+        if self.capture.queue_capacity == 0 {
+            return Err(ConfigError::Invalid(
+                "capture.queue_capacity must be at least 1".to_string(),
+            ));
+        }
+        if self.capture.queue_capacity > self.capture.max_queue_capacity {
+            return Err(ConfigError::Invalid(format!(
+                "capture.queue_capacity {} is larger than capture.max_queue_capacity {}",
+                self.capture.queue_capacity, self.capture.max_queue_capacity
+            )));
+        }
+        Ok(())
+    }
+
+    // Effective configuration in TOML, with the password hidden.
+    pub fn display(&self) -> String {
+        let mut shown = self.clone();
+        if shown.broker.password.is_some() {
+            shown.broker.password = Some("********".to_string());
+        }
+        toml::to_string_pretty(&shown).unwrap_or_else(|e| format!("{:?}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cli(args: &[&str]) -> Cli {
+        Cli::try_parse_from(std::iter::once("core").chain(args.iter().copied())).unwrap()
+    }
+
+    fn from_toml(name: &str, raw: &str) -> Result<Config, ConfigError> {
+        let path = std::env::temp_dir().join(format!("{}-{}.toml", name, std::process::id()));
+        fs::write(&path, raw).unwrap();
+        let config = Config::from_file(&path);
+        fs::remove_file(&path).unwrap();
+        config
+    }
+
+    fn invalid(config: &Config) -> String {
+        match config.validate() {
+            Err(ConfigError::Invalid(msg)) => msg,
+            res => panic!("expected an invalid config, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn reads_the_config_file() {
+        let config = from_toml(
+            "reads-the-config-file",
+            r#"
+            [broker]
+            host = "broker.local"
+            [qos]
+            frame = 0
+            [cameras.aliases]
+            "svbony-1234" = "guider"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.broker.host, "broker.local");
+        assert_eq!(config.broker.port, 1883);
+        assert_eq!(config.qos.frame, 0);
+        assert_eq!(config.cameras.camera_id("svbony", "1234"), "guider");
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        for raw in ["hots = \"broker.local\"", "[broker]\nhots = \"broker.local\""] {
+            let res = from_toml("rejects-unknown-fields", raw);
+            assert!(matches!(res, Err(ConfigError::Parse(..))), "{:?}", res);
+        }
+    }
+
+    #[test]
+    fn command_line_overrides_the_file() {
+        let mut config = from_toml(
+            "command-line-overrides-the-file",
+            r#"
+            [broker]
+            host = "broker.local"
+            port = 1884
+            [capture]
+            queue_capacity = 4
+            "#,
+        )
+        .unwrap();
+        config
+            .apply(cli(&["--port", "8883", "--qos-frame", "0", "--backends", "mock"]))
+            .unwrap();
+        assert_eq!(config.broker.host, "broker.local");
+        assert_eq!(config.broker.port, 8883);
+        assert_eq!(config.qos.frame, 0);
+        assert_eq!(config.qos.command, 2);
+        assert_eq!(config.cameras.backends, ["mock"]);
+        assert_eq!(config.capture.queue_capacity, 4);
+    }
+
+    #[test]
+    fn rejects_a_client_certificate_without_tls() {
+        let mut config = Config::default();
+        let res = config.apply(cli(&["--tls-cert", "client.pem", "--tls-key", "client.key"]));
+        assert!(matches!(res, Err(ConfigError::Invalid(_))), "{:?}", res);
+    }
+
+    #[test]
+    fn validates_the_client_certificate() {
+        let mut config = Config::default();
+        config.broker.tls = Some(TlsConfig {
+            ca_file: "ca.pem".into(),
+            client_cert_file: Some("client.pem".into()),
+            client_key_file: None,
+            client_key_type: ClientKeyType::default(),
+        });
+        assert!(invalid(&config).contains("client_key_file"));
+    }
+
+    #[test]
+    fn validates_the_qos() {
+        let mut config = Config::default();
+        config.qos.status = 3;
+        assert!(invalid(&config).contains("qos.status"));
+    }
+
+    #[test]
+    fn validates_the_aliases() {
+        let mut config = Config::default();
+        config.cameras.aliases.insert("svbony-1".to_string(), "guider".to_string());
+        config.cameras.aliases.insert("svbony-2".to_string(), "guider".to_string());
+        assert!(invalid(&config).contains("several cameras"));
+
+        let mut config = Config::default();
+        config.cameras.aliases.insert("svbony-1".to_string(), "0".to_string());
+        assert!(invalid(&config).contains("not a valid camera id"));
+    }
+
+    #[test]
+    fn mock_camera_count_keeps_the_configured_cameras() {
+        let guider = MockCameraSpec {
+            name: "Guider".to_string(),
+            max_width: 640,
+            max_height: 480,
+            ..MockCameraSpec::default()
+        };
+        let current = vec![guider.clone(), MockCameraSpec::default()];
+        assert_eq!(parse_mock_cameras("1", &current).unwrap(), &current[..1]);
+        assert_eq!(
+            parse_mock_cameras(" 3 ", &current).unwrap(),
+            [guider, MockCameraSpec::default(), MockCameraSpec::default()]
+        );
+        assert!(parse_mock_cameras("0", &current).unwrap().is_empty());
+    }
+
+    #[test]
+    fn mock_cameras_from_a_toml_array() {
+        let cameras = parse_mock_cameras(
+            r#"[{ name = "Guider", max_width = 640, max_height = 480 }, { is_coolable = true }]"#,
+            &[],
+        )
+        .unwrap();
+        assert_eq!(cameras.len(), 2);
+        assert_eq!(cameras[0].name, "Guider");
+        assert_eq!((cameras[0].max_width, cameras[0].max_height), (640, 480));
+        assert_eq!(cameras[0].supported_bins, MockCameraSpec::default().supported_bins);
+        assert!(cameras[1].is_coolable);
+    }
+
+    #[test]
+    fn rejects_bad_mock_cameras() {
+        let values = ["-1", "two", "[{ name = 1 }]", "[{ width = 640 }]", "{ name = \"Guider\" }"];
+        for value in values {
+            let res = parse_mock_cameras(value, &[]);
+            assert!(matches!(res, Err(ConfigError::Invalid(_))), "{}: {:?}", value, res);
+        }
+    }
+}
@@ -0,0 +1,231 @@
+///
+/// Binary frame payload published on `camera/frame`.
+///
+/// Every message is a fixed size little endian header, the frame metadata
+/// encoded in JSON and the raw pixel data, so clients do not have to decode
+/// base64 per frame.
+///
+///  offset  size  field
+///   0       4    magic       "MCSF"
+///   4       1    version     FRAME_HEADER_VERSION
+///   5       1    img_type    ImgType as u8
+///   6       1    bin
+///   7       1    bit_depth
+///   8       2    camera_idx  position of the camera in the inventory, which changes
+///                              when cameras are plugged or unplugged : use camera_id
+///                              of the metadata to identify the camera
+///  10       2    reserved
+///  12       8    seq         frame number since StartCapture, starting at 0
+///  20       8    timestamp   unix time in milliseconds (UTC)
+///  28       4    width
+///  32       4    height
+///  36       4    meta_len
+///  40       ..   metadata    FrameMetadata in JSON, meta_len bytes
+///  ..       ..   pixels
+///
+use camera_driver::interface::{CameraInterface, ControlType, Frame, ImgType, ROIFormat};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+pub const FRAME_MAGIC: &[u8; 4] = b"MCSF";
+pub const FRAME_HEADER_VERSION: u8 = 2;
+pub const FRAME_HEADER_LEN: usize = 40;
+
+// How the frames of a capture are delivered.
+//  binary : header + pixels on `camera/frame`
+//  base64 : FrameData responses on `camera/responce` (compatibility mode for legacy clients)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameEncoding {
+    #[default]
+    Binary,
+    Base64,
+}
+
+impl FromStr for FrameEncoding {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "binary" => Ok(FrameEncoding::Binary),
+            "base64" => Ok(FrameEncoding::Base64),
+            _ => Err(format!("unknown frame encoding {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for FrameEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FrameEncoding::Binary => "binary",
+            FrameEncoding::Base64 => "base64",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+//
+// Metadata of one frame, read from the camera right after the frame itself.
+// {    camera_id,          (stable camera id, see inventory.rs)
+//      seq,
+//      timestamp,          (UTC, RFC 3339)
+//      exposure,
+//      gain,
+//      temperature,        (null when the camera has no sensor temperature)
+//      roi,
+//      img_type
+// }
+// Control values are reported in the units of the vendor SDK.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FrameMetadata {
+    pub camera_id: String,
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    pub exposure: Option<i64>,
+    pub gain: Option<i64>,
+    pub temperature: Option<i64>,
+    pub roi: ROIFormat,
+    pub img_type: Option<ImgType>,
+}
+
+impl FrameMetadata {
+    pub fn collect<T: CameraInterface>(camera: &T, camera_id: &str, seq: u64) -> Self {
+        let roi = camera.get_roi();
+        FrameMetadata {
+            camera_id: camera_id.to_string(),
+            seq,
+            timestamp: Utc::now(),
+            exposure: camera.get_control_value(ControlType::EXPOSURE).ok(),
+            gain: camera.get_control_value(ControlType::GAIN).ok(),
+            temperature: camera
+                .get_control_value(ControlType::CURRENT_TEMPERATURE)
+                .ok(),
+            img_type: Some(ImgType::from_i32(&(roi.img_type as i32))),
+            roi,
+        }
+    }
+}
+
+// A frame read by the capture worker, not yet encoded for the transport.
+#[derive(Debug, Clone)]
+pub struct CapturedFrame {
+    pub meta: FrameMetadata,
+    pub frame: Frame,
+}
+
+impl CapturedFrame {
+    pub fn header(&self, camera_idx: u16) -> FrameHeader {
+        FrameHeader {
+            img_type: self.frame.img_type as u8,
+            bin: self.meta.roi.bin,
+            bit_depth: self.frame.bit_depth,
+            camera_idx,
+            seq: self.meta.seq,
+            timestamp_ms: self.meta.timestamp.timestamp_millis(),
+            width: self.frame.width,
+            height: self.frame.height,
+        }
+    }
+
+    // Header, metadata and pixel data, ready to be published.
+    pub fn encode(&self, camera_idx: u16) -> Result<Vec<u8>, serde_json::Error> {
+        let meta = serde_json::to_vec(&self.meta)?;
+        Ok(self.header(camera_idx).encode(&meta, &self.frame.data))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub img_type: u8,
+    pub bin: u8,
+    pub bit_depth: u8,
+    pub camera_idx: u16,
+    pub seq: u64,
+    pub timestamp_ms: i64,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FrameHeader {
+    pub fn encode(&self, meta: &[u8], pixels: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(FRAME_HEADER_LEN + meta.len() + pixels.len());
+        buf.extend_from_slice(FRAME_MAGIC);
+        buf.push(FRAME_HEADER_VERSION);
+        buf.push(self.img_type);
+        buf.push(self.bin);
+        buf.push(self.bit_depth);
+        buf.extend_from_slice(&self.camera_idx.to_le_bytes());
+        buf.extend_from_slice(&[0u8; 2]);
+        buf.extend_from_slice(&self.seq.to_le_bytes());
+        buf.extend_from_slice(&self.timestamp_ms.to_le_bytes());
+        buf.extend_from_slice(&self.width.to_le_bytes());
+        buf.extend_from_slice(&self.height.to_le_bytes());
+        buf.extend_from_slice(&(meta.len() as u32).to_le_bytes());
+        buf.extend_from_slice(meta);
+        buf.extend_from_slice(pixels);
+        buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use camera_driver::mock::{MockCamera, MockCameraSpec};
+
+    fn le<const N: usize>(buf: &[u8], offset: usize) -> [u8; N] {
+        buf[offset..offset + N].try_into().unwrap()
+    }
+
+    #[test]
+    fn header_fields_are_at_their_documented_offsets() {
+        let header = FrameHeader {
+            img_type: ImgType::RAW16 as u8,
+            bin: 2,
+            bit_depth: 16,
+            camera_idx: 0x0102,
+            seq: 0x0304_0506_0708_090a,
+            timestamp_ms: 1_700_000_000_123,
+            width: 956,
+            height: 652,
+        };
+        let meta = br#"{"seq":1}"#;
+        let pixels = [0xaa, 0xbb, 0xcc];
+        let buf = header.encode(meta, &pixels);
+
+        assert_eq!(FRAME_HEADER_LEN, 40);
+        assert_eq!(buf.len(), FRAME_HEADER_LEN + meta.len() + pixels.len());
+        assert_eq!(&buf[0..4], FRAME_MAGIC);
+        assert_eq!(buf[4], FRAME_HEADER_VERSION);
+        assert_eq!(buf[5], ImgType::RAW16 as u8);
+        assert_eq!(buf[6], 2);
+        assert_eq!(buf[7], 16);
+        assert_eq!(u16::from_le_bytes(le(&buf, 8)), 0x0102);
+        assert_eq!(&buf[10..12], &[0, 0]);
+        assert_eq!(u64::from_le_bytes(le(&buf, 12)), 0x0304_0506_0708_090a);
+        assert_eq!(i64::from_le_bytes(le(&buf, 20)), 1_700_000_000_123);
+        assert_eq!(u32::from_le_bytes(le(&buf, 28)), 956);
+        assert_eq!(u32::from_le_bytes(le(&buf, 32)), 652);
+        assert_eq!(u32::from_le_bytes(le(&buf, 36)) as usize, meta.len());
+        assert_eq!(&buf[FRAME_HEADER_LEN..FRAME_HEADER_LEN + meta.len()], meta);
+        assert_eq!(&buf[FRAME_HEADER_LEN + meta.len()..], &pixels);
+    }
+
+    #[test]
+    fn encoded_frame_carries_the_camera_id_in_the_metadata() {
+        let camera = MockCamera::new(0, MockCameraSpec::default()).unwrap();
+        let frame = CapturedFrame {
+            meta: FrameMetadata::collect(&camera, "mock-MOCK0000", 7),
+            frame: Frame::new(vec![1, 2, 3, 4], 2, 1, ImgType::RAW16),
+        };
+        let buf = frame.encode(3).unwrap();
+        assert_eq!(u16::from_le_bytes(le(&buf, 8)), 3);
+        assert_eq!(u64::from_le_bytes(le(&buf, 12)), 7);
+        let meta_len = u32::from_le_bytes(le(&buf, 36)) as usize;
+        let meta: FrameMetadata =
+            serde_json::from_slice(&buf[FRAME_HEADER_LEN..FRAME_HEADER_LEN + meta_len]).unwrap();
+        assert_eq!(meta.camera_id, "mock-MOCK0000");
+        assert_eq!(meta.seq, 7);
+        assert_eq!(&buf[FRAME_HEADER_LEN + meta_len..], &[1, 2, 3, 4]);
+    }
+}
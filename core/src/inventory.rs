@@ -0,0 +1,53 @@
+///
+/// Device inventory, published retained on `<prefix>/inventory` whenever the
+/// list of opened cameras changes or a camera starts or stops capturing, so
+/// clients can discover the cameras without sending camera/init.
+///
+/// {   num_device,
+///     cameras : [
+///         {   id,             (stable camera id, used in the per camera topics <prefix>/<id>/...)
+///             idx,            (camera_idx of the camera until the next rescan)
+///             vendor,         ("mock" | "svbony")
+///             serial,         (serial number reported by the backend)
+///             info,           (same as the GetInfo responce data)
+///             state           ("idle" | "capturing")
+///         }, ...
+///     ]
+/// }
+///
+/// The live state of a camera is published on `<prefix>/<id>/status`.
+///
+use camera_driver::interface::CameraInfo;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceActivity {
+    Idle,
+    Capturing,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InventoryEntry {
+    pub id: String,
+    pub idx: usize,
+    pub vendor: String,
+    pub serial: String,
+    pub info: CameraInfo,
+    pub state: DeviceActivity,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Inventory {
+    pub num_device: usize,
+    pub cameras: Vec<InventoryEntry>,
+}
+
+impl Inventory {
+    pub fn new(cameras: Vec<InventoryEntry>) -> Self {
+        Self {
+            num_device: cameras.len(),
+            cameras,
+        }
+    }
+}
@@ -1,5 +1,2 @@
 extern crate env_logger;
 extern crate log;
-use log::{debug, error, info, warn};
-
-mod server;
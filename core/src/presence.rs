@@ -0,0 +1,50 @@
+///
+/// Availability of the server, published retained on `<prefix>/presence`.
+///
+/// {   state : "online" | "offline",
+///     version,            (version of the server)
+///     client_id,
+///     num_device          (null in the offline message)
+/// }
+///
+/// The online message is published once connected and whenever the number of
+/// cameras changes. The offline message is registered as last will, so the
+/// broker publishes it when the server disconnects without notice.
+///
+use serde::{Deserialize, Serialize};
+
+pub const SERVER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PresenceState {
+    Online,
+    Offline,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Presence {
+    pub state: PresenceState,
+    pub version: String,
+    pub client_id: String,
+    pub num_device: Option<usize>,
+}
+
+impl Presence {
+    pub fn online(client_id: &str, num_device: usize) -> Self {
+        Self {
+            state: PresenceState::Online,
+            version: SERVER_VERSION.to_string(),
+            client_id: client_id.to_string(),
+            num_device: Some(num_device),
+        }
+    }
+    pub fn offline(client_id: &str) -> Self {
+        Self {
+            state: PresenceState::Offline,
+            version: SERVER_VERSION.to_string(),
+            client_id: client_id.to_string(),
+            num_device: None,
+        }
+    }
+}
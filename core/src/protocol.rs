@@ -0,0 +1,908 @@
+///
+/// Wire format of the commands received on `camera/instr` / `camera/init`
+/// and of the responses published on `camera/responce`.
+///
+/// Two request forms are accepted:
+///
+/// typed (preferred) :
+/// {   transaction_id : string,
+///     camera_idx : int,       (optional, see below)
+///     camera_id : string,     (optional, see below)
+///     cmd : "set_roi",
+///     args : { startx : 0, starty : 0, width : 1912, height : 1304, bin : 1, img_type : "RAW8" }
+/// }
+/// Commands without arguments (get_info, stop_capture, ...) omit args or send {}.
+///
+/// legacy :
+/// {   transaction_id : string,
+///     camera_idx : int,
+///     cmd_idx : int,
+///     data : { "startx" : "0", ... }
+/// }
+///
+/// A camera is selected by its stable camera_id (see inventory.rs), or by
+/// camera_idx, its position in the inventory, when camera_id is missing.
+/// Positions change when cameras are plugged or unplugged.
+/// Typed requests on `camera/instr` must give at least one of them, on a per
+/// camera topic the camera is taken from the topic.
+///
+/// Responses are encoded in the same form as the request they answer.
+/// Frames of a binary capture are not wrapped in a response, they are
+/// published on `camera/frame` (see frame.rs).
+///
+use crate::frame::{FrameEncoding, FrameMetadata};
+use crate::queue::DropPolicy;
+use crate::status::CameraStatus;
+use camera_driver::interface::{
+    CameraError, CameraInfo, ControlCaps, ControlType, ImgType, ROIFormat,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+// Reason a command could not be executed, reported back to the client in the response envelope.
+#[derive(Debug, Clone)]
+pub enum CmdError {
+    MissingField(String),
+    InvalidValue { field: String, value: String },
+    UnknownCommand(i32),
+    UnknownCamera(String),
+    MissingCamera,
+    InvalidPayload(String),
+    AlreadyCapturing,
+    ReadOnlyControl(ControlType),
+    AutoNotSupported(ControlType),
+    Camera(CameraError),
+    Internal(String),
+}
+impl CmdError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            CmdError::MissingField(_) => "missing_field",
+            CmdError::InvalidValue { .. } => "invalid_value",
+            CmdError::UnknownCommand(_) => "unknown_command",
+            CmdError::UnknownCamera(_) => "unknown_camera",
+            CmdError::MissingCamera => "missing_camera",
+            CmdError::InvalidPayload(_) => "invalid_payload",
+            CmdError::AlreadyCapturing => "already_capturing",
+            CmdError::ReadOnlyControl(_) => "read_only_control",
+            CmdError::AutoNotSupported(_) => "auto_not_supported",
+            CmdError::Camera(e) => e.code(),
+            CmdError::Internal(_) => "internal",
+        }
+    }
+}
+impl fmt::Display for CmdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CmdError::MissingField(field) => write!(f, "missing field `{}` in data", field),
+            CmdError::InvalidValue { field, value } => {
+                write!(f, "invalid value {:?} for field `{}`", value, field)
+            }
+            CmdError::UnknownCommand(cmd_idx) => write!(f, "unknown command index {}", cmd_idx),
+            CmdError::UnknownCamera(camera) => {
+                write!(f, "no camera connected with id or camera_idx {}", camera)
+            }
+            CmdError::MissingCamera => write!(f, "request needs camera_id or camera_idx"),
+            CmdError::InvalidPayload(msg) => write!(f, "invalid payload: {}", msg),
+            CmdError::AlreadyCapturing => write!(f, "camera is already capturing"),
+            CmdError::ReadOnlyControl(ctrl_type) => {
+                write!(f, "control {:?} is read only", ctrl_type)
+            }
+            CmdError::AutoNotSupported(ctrl_type) => {
+                write!(f, "control {:?} does not support auto mode", ctrl_type)
+            }
+            CmdError::Camera(e) => write!(f, "{}", e),
+            CmdError::Internal(msg) => write!(f, "internal error: {}", msg),
+        }
+    }
+}
+impl From<CameraError> for CmdError {
+    fn from(e: CameraError) -> Self {
+        CmdError::Camera(e)
+    }
+}
+impl From<serde_json::Error> for CmdError {
+    fn from(e: serde_json::Error) -> Self {
+        CmdError::Internal(e.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CtrlTypeArgs {
+    pub ctrl_type: ControlType,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetCtrlValArgs {
+    pub ctrl_type: ControlType,
+    pub value: i64,
+    #[serde(default)]
+    pub is_auto: bool,
+}
+
+// Frame queue and delivery settings of a capture, server defaults are used for missing fields.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StartCaptureArgs {
+    #[serde(default)]
+    pub drop_policy: Option<DropPolicy>,
+    #[serde(default)]
+    pub queue_capacity: Option<usize>,
+    #[serde(default)]
+    pub encoding: Option<FrameEncoding>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetRoiArgs {
+    pub startx: u32,
+    pub starty: u32,
+    pub width: u32,
+    pub height: u32,
+    pub bin: u8,
+    pub img_type: ImgType,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "cmd", content = "args", rename_all = "snake_case")]
+pub enum Command {
+    GetInfo,
+    GetStatus,
+    GetRoi,
+    GetCtrlVal(CtrlTypeArgs),
+    SetRoi(SetRoiArgs),
+    SetCtrlVal(SetCtrlValArgs),
+    StartCapture(Option<StartCaptureArgs>),
+    StopCapture,
+    Init,
+    #[serde(rename = "adjust_wb")]
+    AdjustWB,
+    GetControlCaps,
+}
+
+impl Command {
+    // Name used for the `cmd` field of typed messages.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::GetInfo => "get_info",
+            Command::GetStatus => "get_status",
+            Command::GetRoi => "get_roi",
+            Command::GetCtrlVal(_) => "get_ctrl_val",
+            Command::SetRoi(_) => "set_roi",
+            Command::SetCtrlVal(_) => "set_ctrl_val",
+            Command::StartCapture(_) => "start_capture",
+            Command::StopCapture => "stop_capture",
+            Command::Init => "init",
+            Command::AdjustWB => "adjust_wb",
+            Command::GetControlCaps => "get_control_caps",
+        }
+    }
+    // Index used for the `cmd_idx` field of legacy messages.
+    pub fn legacy_idx(&self) -> i32 {
+        match self {
+            Command::GetInfo => 0,
+            Command::GetStatus => 1,
+            Command::GetRoi => 2,
+            Command::GetCtrlVal(_) => 3,
+            Command::SetRoi(_) => 4,
+            Command::SetCtrlVal(_) => 5,
+            Command::StartCapture(_) => 6,
+            Command::StopCapture => 7,
+            Command::Init => 8,
+            Command::AdjustWB => 9,
+            Command::GetControlCaps => 10,
+        }
+    }
+    // Commands after which the camera status is republished.
+    pub fn changes_state(&self) -> bool {
+        matches!(
+            self,
+            Command::SetRoi(_)
+                | Command::SetCtrlVal(_)
+                | Command::StartCapture(_)
+                | Command::StopCapture
+                | Command::AdjustWB
+        )
+    }
+    // Commands after which the inventory is republished, they change the camera activity.
+    pub fn changes_activity(&self) -> bool {
+        matches!(self, Command::StartCapture(_) | Command::StopCapture)
+    }
+    // Builds a command from the legacy `cmd_idx` and string map.
+    pub fn from_legacy(cmd_idx: i32, data: &HashMap<String, String>) -> Result<Command, CmdError> {
+        let cmd = match cmd_idx {
+            0 => Command::GetInfo,
+            1 => Command::GetStatus,
+            2 => Command::GetRoi,
+            3 => Command::GetCtrlVal(CtrlTypeArgs {
+                ctrl_type: parse_ctrl_type(data)?,
+            }),
+            4 => {
+                let img_type_idx: i32 = parse_field(data, "img_type")?;
+                Command::SetRoi(SetRoiArgs {
+                    startx: parse_field(data, "startx")?,
+                    starty: parse_field(data, "starty")?,
+                    width: parse_field(data, "width")?,
+                    height: parse_field(data, "height")?,
+                    bin: parse_field(data, "bin")?,
+                    img_type: ImgType::from_i32(&img_type_idx),
+                })
+            }
+            5 => {
+                let is_auto: i64 = parse_optional_field(data, "is_auto")?.unwrap_or(0);
+                Command::SetCtrlVal(SetCtrlValArgs {
+                    ctrl_type: parse_ctrl_type(data)?,
+                    value: parse_field(data, "value")?,
+                    is_auto: is_auto != 0,
+                })
+            }
+            6 => Command::StartCapture(Some(StartCaptureArgs {
+                drop_policy: parse_optional_field(data, "drop_policy")?,
+                queue_capacity: parse_optional_field(data, "queue_capacity")?,
+                // Legacy clients expect base64 frames in the responce envelope.
+                encoding: Some(
+                    parse_optional_field(data, "encoding")?.unwrap_or(FrameEncoding::Base64),
+                ),
+            })),
+            7 => Command::StopCapture,
+            8 => Command::Init,
+            9 => Command::AdjustWB,
+            10 => Command::GetControlCaps,
+            _ => return Err(CmdError::UnknownCommand(cmd_idx)),
+        };
+        Ok(cmd)
+    }
+}
+
+// Reads `key` from the string map sent by a legacy client and parses it.
+fn parse_field<T: FromStr>(data: &HashMap<String, String>, key: &str) -> Result<T, CmdError> {
+    let raw = data
+        .get(key)
+        .ok_or_else(|| CmdError::MissingField(key.to_string()))?;
+    raw.trim().parse().map_err(|_| CmdError::InvalidValue {
+        field: key.to_string(),
+        value: raw.to_string(),
+    })
+}
+
+fn parse_optional_field<T: FromStr>(
+    data: &HashMap<String, String>,
+    key: &str,
+) -> Result<Option<T>, CmdError> {
+    match data.get(key) {
+        Some(_) => parse_field(data, key).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn parse_ctrl_type(data: &HashMap<String, String>) -> Result<ControlType, CmdError> {
+    let ctrl_type_idx: i32 = parse_field(data, "ctrl_type")?;
+    ControlType::from_i32(&ctrl_type_idx).map_err(|_| CmdError::InvalidValue {
+        field: "ctrl_type".to_string(),
+        value: ctrl_type_idx.to_string(),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireFormat {
+    Legacy,
+    Typed,
+}
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub transaction_id: String,
+    pub camera_idx: i32,
+    pub camera_id: Option<String>,
+    pub command: Command,
+    pub format: WireFormat,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TypedRequest {
+    transaction_id: String,
+    #[serde(default)]
+    camera_idx: Option<i32>,
+    #[serde(default)]
+    camera_id: Option<String>,
+    #[serde(flatten)]
+    command: Command,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LegacyRequest {
+    transaction_id: String,
+    camera_idx: i32,
+    #[serde(default)]
+    camera_id: Option<String>,
+    cmd_idx: i32,
+    #[serde(default)]
+    data: HashMap<String, String>,
+}
+
+// A message that could not be decoded into a Request, together with whatever
+// identifiers could still be recovered so the client can correlate the error.
+#[derive(Debug)]
+pub struct Rejected {
+    pub transaction_id: String,
+    pub camera_idx: i32,
+    pub camera_id: Option<String>,
+    pub format: WireFormat,
+    cmd_idx: i32,
+    cmd: String,
+    pub error: CmdError,
+}
+
+impl Rejected {
+    pub fn reply(&self) -> Result<String, serde_json::Error> {
+        encode_reply(
+            self.format,
+            &self.transaction_id,
+            self.camera_idx,
+            self.camera_id.as_deref(),
+            self.cmd_idx,
+            &self.cmd,
+            Err(&self.error),
+        )
+    }
+}
+
+impl Request {
+    // False for a typed request without camera_id and camera_idx.
+    pub fn names_camera(&self) -> bool {
+        self.camera_id.is_some() || self.camera_idx >= 0
+    }
+
+    // The rejection is boxed, it is much larger than a Request.
+    pub fn decode(raw: &[u8]) -> Result<Request, Box<Rejected>> {
+        let reject = |value: Option<&serde_json::Value>, error: CmdError| {
+            let field = |key: &str| value.and_then(|v| v.get(key));
+            Box::new(Rejected {
+                transaction_id: field("transaction_id")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default()
+                    .to_string(),
+                camera_idx: field("camera_idx")
+                    .and_then(|v| v.as_i64())
+                    .map_or(-1, |v| v as i32),
+                camera_id: field("camera_id")
+                    .and_then(|v| v.as_str())
+                    .map(|v| v.to_string()),
+                format: match field("cmd") {
+                    Some(_) => WireFormat::Typed,
+                    None => WireFormat::Legacy,
+                },
+                cmd_idx: field("cmd_idx")
+                    .and_then(|v| v.as_i64())
+                    .map_or(-1, |v| v as i32),
+                cmd: field("cmd")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default()
+                    .to_string(),
+                error,
+            })
+        };
+        let payload = std::str::from_utf8(raw)
+            .map_err(|e| reject(None, CmdError::InvalidPayload(e.to_string())))?;
+        let value: serde_json::Value = serde_json::from_str(payload)
+            .map_err(|e| reject(None, CmdError::InvalidPayload(e.to_string())))?;
+
+        if value.get("cmd_idx").is_some() {
+            let req = LegacyRequest::deserialize(&value)
+                .map_err(|e| reject(Some(&value), CmdError::InvalidPayload(e.to_string())))?;
+            let command = Command::from_legacy(req.cmd_idx, &req.data)
+                .map_err(|e| reject(Some(&value), e))?;
+            Ok(Request {
+                transaction_id: req.transaction_id,
+                camera_idx: req.camera_idx,
+                camera_id: req.camera_id,
+                command,
+                format: WireFormat::Legacy,
+            })
+        } else {
+            // Commands without arguments are unit variants, which reject "args": {}.
+            let req = TypedRequest::deserialize(&value)
+                .or_else(|e| {
+                    without_empty_args(&value)
+                        .and_then(|value| TypedRequest::deserialize(&value).ok())
+                        .ok_or(e)
+                })
+                .map_err(|e| reject(Some(&value), CmdError::InvalidPayload(e.to_string())))?;
+            Ok(Request {
+                transaction_id: req.transaction_id,
+                camera_idx: req.camera_idx.unwrap_or(-1),
+                camera_id: req.camera_id,
+                command: req.command,
+                format: WireFormat::Typed,
+            })
+        }
+    }
+
+    // Encodes the result of this request in the envelope the client expects.
+    pub fn reply(&self, result: Result<&Response, &CmdError>) -> Result<String, serde_json::Error> {
+        encode_reply(
+            self.format,
+            &self.transaction_id,
+            self.camera_idx,
+            self.camera_id.as_deref(),
+            self.command.legacy_idx(),
+            self.command.name(),
+            result,
+        )
+    }
+}
+
+// The typed message without its args field, when args is an empty object.
+fn without_empty_args(value: &serde_json::Value) -> Option<serde_json::Value> {
+    let mut value = value.clone();
+    let fields = value.as_object_mut()?;
+    match fields.get("args") {
+        Some(serde_json::Value::Object(args)) if args.is_empty() => {
+            fields.remove("args");
+            Some(value)
+        }
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CtrlValue {
+    pub ctrl_type: ControlType,
+    pub value: i64,
+}
+
+// Answer to SetCtrlVal: `value` is read back from the camera after writing `applied`,
+// which differs from `requested` when the request was clamped to the control range.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppliedCtrlValue {
+    pub ctrl_type: ControlType,
+    pub value: i64,
+    pub requested: i64,
+    pub applied: i64,
+    pub clamped: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FrameData {
+    pub frame: String,
+    pub meta: FrameMetadata,
+}
+
+// A camera opened or retired by a rescan.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceChange {
+    pub id: String,
+    pub vendor: String,
+    pub name: String,
+    pub serial: String,
+}
+
+// A camera listed by a rescan that could not be opened.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceFailure {
+    #[serde(flatten)]
+    pub device: DeviceChange,
+    pub error: String,
+}
+
+// Answer to Init: cameras that stayed connected are kept open and are not listed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InitData {
+    pub num_device: usize,
+    pub added: Vec<DeviceChange>,
+    pub removed: Vec<DeviceChange>,
+    pub failed: Vec<DeviceFailure>,
+}
+
+// Data returned by a command. Each variant is the answer to the command of the same name.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum Response {
+    Info(CameraInfo),
+    Status(CameraStatus),
+    Roi(ROIFormat),
+    CtrlVal(CtrlValue),
+    AppliedCtrlVal(AppliedCtrlValue),
+    ControlCaps(Vec<ControlCaps>),
+    Frame(FrameData),
+    Init(InitData),
+    Empty {},
+}
+
+impl Response {
+    // Legacy clients expect the data field as a JSON encoded string, with
+    // control values and device counts given as strings.
+    fn to_legacy_data(&self) -> Result<String, serde_json::Error> {
+        match self {
+            Response::CtrlVal(ctrl) => {
+                let mut res = HashMap::new();
+                res.insert("ctrl_type", (ctrl.ctrl_type as i32).to_string());
+                res.insert("value", ctrl.value.to_string());
+                serde_json::to_string(&res)
+            }
+            Response::AppliedCtrlVal(ctrl) => {
+                let mut res = HashMap::new();
+                res.insert("ctrl_type", (ctrl.ctrl_type as i32).to_string());
+                res.insert("value", ctrl.value.to_string());
+                res.insert("requested", ctrl.requested.to_string());
+                res.insert("applied", ctrl.applied.to_string());
+                res.insert("clamped", ctrl.clamped.to_string());
+                serde_json::to_string(&res)
+            }
+            Response::Init(init) => {
+                let mut res = HashMap::new();
+                res.insert("num_device", init.num_device.to_string());
+                res.insert("added", serde_json::to_string(&init.added)?);
+                res.insert("removed", serde_json::to_string(&init.removed)?);
+                res.insert("failed", serde_json::to_string(&init.failed)?);
+                serde_json::to_string(&res)
+            }
+            res => serde_json::to_string(res),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResponceStatus {
+    Ok,
+    Error,
+}
+
+// Envelope of legacy responses.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Responce {
+    transaction_id: String,
+    camera_idx: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    camera_id: Option<String>,
+    cmd_idx: String,
+    status: ResponceStatus,
+    error_code: Option<String>,
+    message: Option<String>,
+    data: String,
+}
+
+// Envelope of typed responses.
+#[derive(Debug, Clone, Serialize)]
+struct TypedResponce<'a> {
+    transaction_id: &'a str,
+    camera_idx: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    camera_id: Option<&'a str>,
+    cmd: &'a str,
+    status: ResponceStatus,
+    error_code: Option<&'a str>,
+    message: Option<String>,
+    data: Option<&'a Response>,
+}
+
+fn encode_reply(
+    format: WireFormat,
+    transaction_id: &str,
+    camera_idx: i32,
+    camera_id: Option<&str>,
+    cmd_idx: i32,
+    cmd: &str,
+    result: Result<&Response, &CmdError>,
+) -> Result<String, serde_json::Error> {
+    match format {
+        WireFormat::Legacy => {
+            let res = match result {
+                Ok(res) => Responce {
+                    transaction_id: transaction_id.to_string(),
+                    camera_idx: camera_idx.to_string(),
+                    camera_id: camera_id.map(|id| id.to_string()),
+                    cmd_idx: cmd_idx.to_string(),
+                    status: ResponceStatus::Ok,
+                    error_code: None,
+                    message: None,
+                    data: res.to_legacy_data()?,
+                },
+                Err(e) => Responce {
+                    transaction_id: transaction_id.to_string(),
+                    camera_idx: camera_idx.to_string(),
+                    camera_id: camera_id.map(|id| id.to_string()),
+                    cmd_idx: cmd_idx.to_string(),
+                    status: ResponceStatus::Error,
+                    error_code: Some(e.code().to_string()),
+                    message: Some(e.to_string()),
+                    data: r#"{}"#.to_string(),
+                },
+            };
+            serde_json::to_string(&res)
+        }
+        WireFormat::Typed => {
+            let res = TypedResponce {
+                transaction_id,
+                camera_idx,
+                camera_id,
+                cmd,
+                status: match result {
+                    Ok(_) => ResponceStatus::Ok,
+                    Err(_) => ResponceStatus::Error,
+                },
+                error_code: result.err().map(|e| e.code()),
+                message: result.err().map(|e| e.to_string()),
+                data: result.ok(),
+            };
+            serde_json::to_string(&res)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn legacy(cmd_idx: i32, data: Value) -> Vec<u8> {
+        json!({
+            "transaction_id": "t1",
+            "camera_idx": 0,
+            "cmd_idx": cmd_idx,
+            "data": data,
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    fn reject(raw: &[u8]) -> Rejected {
+        match Request::decode(raw) {
+            Ok(req) => panic!("decoded {:?}", req.command),
+            Err(rejected) => *rejected,
+        }
+    }
+
+    #[test]
+    fn decodes_every_legacy_cmd_idx() {
+        let cases = [
+            (0, json!({})),
+            (1, json!({})),
+            (2, json!({})),
+            (3, json!({ "ctrl_type": "1" })),
+            (
+                4,
+                json!({
+                    "startx": "0", "starty": "0", "width": "1912", "height": "1304",
+                    "bin": "1", "img_type": "0"
+                }),
+            ),
+            (5, json!({ "ctrl_type": "0", "value": "100" })),
+            (6, json!({})),
+            (7, json!({})),
+            (8, json!({})),
+            (9, json!({})),
+            (10, json!({})),
+        ];
+        for (cmd_idx, data) in cases {
+            let req = Request::decode(&legacy(cmd_idx, data)).unwrap();
+            assert_eq!(req.format, WireFormat::Legacy);
+            assert_eq!(req.transaction_id, "t1");
+            assert_eq!(req.command.legacy_idx(), cmd_idx);
+        }
+    }
+
+    #[test]
+    fn legacy_arguments_are_parsed_from_strings() {
+        let data = json!({
+            "startx": "8", "starty": " 2 ", "width": "640", "height": "480",
+            "bin": "2", "img_type": "4"
+        });
+        match Request::decode(&legacy(4, data)).unwrap().command {
+            Command::SetRoi(args) => {
+                assert_eq!((args.startx, args.starty, args.width, args.height), (8, 2, 640, 480));
+                assert_eq!(args.bin, 2);
+                assert_eq!(args.img_type, ImgType::RAW16);
+            }
+            cmd => panic!("unexpected command {:?}", cmd),
+        }
+        let data = json!({ "ctrl_type": "1", "value": "5000", "is_auto": "1" });
+        match Request::decode(&legacy(5, data)).unwrap().command {
+            Command::SetCtrlVal(args) => {
+                assert_eq!(args.ctrl_type, ControlType::EXPOSURE);
+                assert_eq!(args.value, 5000);
+                assert!(args.is_auto);
+            }
+            cmd => panic!("unexpected command {:?}", cmd),
+        }
+        // Legacy captures default to base64 frames in the responce envelope.
+        match Request::decode(&legacy(6, json!({}))).unwrap().command {
+            Command::StartCapture(Some(args)) => {
+                assert_eq!(args.encoding, Some(FrameEncoding::Base64));
+                assert_eq!(args.drop_policy, None);
+            }
+            cmd => panic!("unexpected command {:?}", cmd),
+        }
+    }
+
+    #[test]
+    fn rejects_bad_legacy_commands() {
+        let rejected = reject(&legacy(42, json!({})));
+        assert!(matches!(rejected.error, CmdError::UnknownCommand(42)));
+        let rejected = reject(&legacy(3, json!({})));
+        assert!(matches!(rejected.error, CmdError::MissingField(ref f) if f == "ctrl_type"));
+        let rejected = reject(&legacy(5, json!({ "ctrl_type": "0", "value": "high" })));
+        assert!(matches!(
+            rejected.error,
+            CmdError::InvalidValue { ref field, .. } if field == "value"
+        ));
+        let rejected = reject(&legacy(3, json!({ "ctrl_type": "99" })));
+        assert!(matches!(
+            rejected.error,
+            CmdError::InvalidValue { ref field, .. } if field == "ctrl_type"
+        ));
+    }
+
+    #[test]
+    fn decodes_typed_commands() {
+        let raw = json!({
+            "transaction_id": "t2",
+            "camera_id": "cam",
+            "cmd": "get_ctrl_val",
+            "args": { "ctrl_type": "GAIN" },
+        });
+        let req = Request::decode(raw.to_string().as_bytes()).unwrap();
+        assert_eq!(req.format, WireFormat::Typed);
+        assert_eq!(req.camera_id.as_deref(), Some("cam"));
+        assert!(matches!(
+            req.command,
+            Command::GetCtrlVal(CtrlTypeArgs {
+                ctrl_type: ControlType::GAIN
+            })
+        ));
+    }
+
+    #[test]
+    fn typed_camera_idx_is_optional() {
+        let req = Request::decode(br#"{"transaction_id":"t4","cmd":"get_info"}"#).unwrap();
+        assert_eq!(req.camera_idx, -1);
+        assert!(!req.names_camera());
+        let req = Request::decode(br#"{"transaction_id":"t4","camera_idx":0,"cmd":"get_info"}"#)
+            .unwrap();
+        assert_eq!(req.camera_idx, 0);
+        assert!(req.names_camera());
+    }
+
+    #[test]
+    fn typed_unit_commands_accept_empty_args() {
+        for raw in [
+            &br#"{"transaction_id":"t3","cmd":"get_info"}"#[..],
+            &br#"{"transaction_id":"t3","cmd":"get_info","args":{}}"#[..],
+        ] {
+            assert!(matches!(Request::decode(raw).unwrap().command, Command::GetInfo));
+        }
+        let raw = br#"{"transaction_id":"t3","cmd":"get_info","args":{"x":1}}"#;
+        assert!(matches!(reject(raw).error, CmdError::InvalidPayload(_)));
+    }
+
+    #[test]
+    fn legacy_rejection_envelope() {
+        let rejected = reject(&legacy(42, json!({})));
+        let res: Value = serde_json::from_str(&rejected.reply().unwrap()).unwrap();
+        assert_eq!(
+            res,
+            json!({
+                "transaction_id": "t1",
+                "camera_idx": "0",
+                "cmd_idx": "42",
+                "status": "error",
+                "error_code": "unknown_command",
+                "message": "unknown command index 42",
+                "data": "{}",
+            })
+        );
+    }
+
+    #[test]
+    fn typed_rejection_envelope() {
+        let raw = json!({
+            "transaction_id": "t4",
+            "camera_idx": 1,
+            "camera_id": "cam",
+            "cmd": "set_roi",
+            "args": {},
+        });
+        let rejected = reject(raw.to_string().as_bytes());
+        let res: Value = serde_json::from_str(&rejected.reply().unwrap()).unwrap();
+        assert_eq!(res["transaction_id"], "t4");
+        assert_eq!(res["camera_idx"], 1);
+        assert_eq!(res["camera_id"], "cam");
+        assert_eq!(res["cmd"], "set_roi");
+        assert_eq!(res["status"], "error");
+        assert_eq!(res["error_code"], "invalid_payload");
+        assert_eq!(res["data"], Value::Null);
+    }
+
+    #[test]
+    fn unparsable_payload_is_rejected_in_legacy_form() {
+        let rejected = reject(b"not json");
+        let res: Value = serde_json::from_str(&rejected.reply().unwrap()).unwrap();
+        assert_eq!(res["transaction_id"], "");
+        assert_eq!(res["camera_idx"], "-1");
+        assert_eq!(res["cmd_idx"], "-1");
+        assert_eq!(res["error_code"], "invalid_payload");
+    }
+
+    fn legacy_data(req: &[u8], res: &Response) -> Value {
+        let req = Request::decode(req).unwrap();
+        let envelope: Value = serde_json::from_str(&req.reply(Ok(res)).unwrap()).unwrap();
+        assert_eq!(envelope["status"], "ok");
+        // The data field is a JSON document encoded as a string.
+        serde_json::from_str(envelope["data"].as_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn legacy_data_is_a_json_string() {
+        let res = Response::CtrlVal(CtrlValue {
+            ctrl_type: ControlType::EXPOSURE,
+            value: 30_000,
+        });
+        let data = legacy_data(&legacy(3, json!({ "ctrl_type": "1" })), &res);
+        assert_eq!(data, json!({ "ctrl_type": "1", "value": "30000" }));
+
+        let res = Response::AppliedCtrlVal(AppliedCtrlValue {
+            ctrl_type: ControlType::GAIN,
+            value: 720,
+            requested: 800,
+            applied: 720,
+            clamped: true,
+        });
+        let data = legacy_data(&legacy(5, json!({ "ctrl_type": "0", "value": "800" })), &res);
+        assert_eq!(
+            data,
+            json!({
+                "ctrl_type": "0", "value": "720", "requested": "800",
+                "applied": "720", "clamped": "true"
+            })
+        );
+
+        let res = Response::Init(InitData {
+            num_device: 1,
+            added: vec![DeviceChange {
+                id: "mock-MOCK0000".to_string(),
+                vendor: "mock".to_string(),
+                name: "Mock Camera".to_string(),
+                serial: "MOCK0000".to_string(),
+            }],
+            removed: Vec::new(),
+            failed: Vec::new(),
+        });
+        let data = legacy_data(&legacy(8, json!({})), &res);
+        assert_eq!(data["num_device"], "1");
+        let added: Value = serde_json::from_str(data["added"].as_str().unwrap()).unwrap();
+        assert_eq!(added[0]["serial"], "MOCK0000");
+        assert_eq!(data["removed"], "[]");
+        assert_eq!(data["failed"], "[]");
+
+        let res = Response::Roi(ROIFormat {
+            startx: 0,
+            starty: 0,
+            width: 640,
+            height: 480,
+            bin: 1,
+            img_type: 0,
+        });
+        let data = legacy_data(&legacy(2, json!({})), &res);
+        assert_eq!(data["width"], 640);
+        assert_eq!(data["img_type"], 0);
+
+        let data = legacy_data(&legacy(7, json!({})), &Response::Empty {});
+        assert_eq!(data, json!({}));
+    }
+
+    #[test]
+    fn typed_reply_carries_data_as_json() {
+        let raw = br#"{"transaction_id":"t5","cmd":"get_ctrl_val","args":{"ctrl_type":"GAIN"}}"#;
+        let req = Request::decode(raw).unwrap();
+        let res = Response::CtrlVal(CtrlValue {
+            ctrl_type: ControlType::GAIN,
+            value: 10,
+        });
+        let envelope: Value = serde_json::from_str(&req.reply(Ok(&res)).unwrap()).unwrap();
+        assert_eq!(envelope["cmd"], "get_ctrl_val");
+        assert_eq!(envelope["status"], "ok");
+        assert_eq!(envelope["error_code"], Value::Null);
+        assert_eq!(envelope["data"], json!({ "ctrl_type": "GAIN", "value": 10 }));
+    }
+}
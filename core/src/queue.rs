@@ -0,0 +1,277 @@
+///
+/// Bounded frame queue between a capture worker and the publisher.
+///
+/// The worker pushes from its blocking thread, the publisher pops from the
+/// async runtime. When the queue is full the `DropPolicy` decides whether the
+/// oldest queued frame is discarded, the new frame is discarded, or the
+/// worker waits until the publisher catches up.
+///
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Condvar, Mutex};
+use tokio::sync::Notify;
+
+pub const DEFAULT_QUEUE_CAPACITY: usize = 8;
+// Largest capacity a StartCapture may ask for, see capture.max_queue_capacity in the config.
+pub const DEFAULT_MAX_QUEUE_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DropPolicy {
+    #[default]
+    DropOldest,
+    DropNewest,
+    Block,
+}
+
+impl FromStr for DropPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop_oldest" => Ok(DropPolicy::DropOldest),
+            "drop_newest" => Ok(DropPolicy::DropNewest),
+            "block" => Ok(DropPolicy::Block),
+            _ => Err(format!("unknown drop policy {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for DropPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DropPolicy::DropOldest => "drop_oldest",
+            DropPolicy::DropNewest => "drop_newest",
+            DropPolicy::Block => "block",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushOutcome {
+    Queued,
+    // The frame was queued but the oldest queued frame was discarded.
+    DroppedOldest,
+    // The frame was discarded.
+    DroppedNewest,
+    // The queue is closed, the frame was discarded.
+    Closed,
+}
+
+#[derive(Debug)]
+struct Inner<T> {
+    items: VecDeque<T>,
+    closed: bool,
+}
+
+#[derive(Debug)]
+pub struct FrameQueue<T> {
+    inner: Mutex<Inner<T>>,
+    not_full: Condvar,
+    not_empty: Notify,
+    capacity: usize,
+    policy: DropPolicy,
+}
+
+impl<T> FrameQueue<T> {
+    pub fn new(capacity: usize, policy: DropPolicy) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            inner: Mutex::new(Inner {
+                // Grows with the queued frames, the capacity may be much larger than needed.
+                items: VecDeque::new(),
+                closed: false,
+            }),
+            not_full: Condvar::new(),
+            not_empty: Notify::new(),
+            capacity,
+            policy,
+        }
+    }
+
+    // Blocking push, must not be called from the async runtime with DropPolicy::Block.
+    pub fn push(&self, item: T) -> PushOutcome {
+        let mut inner = self.inner.lock().unwrap();
+        // Checked first so a closed queue never evicts the frames still to be popped.
+        if inner.closed {
+            return PushOutcome::Closed;
+        }
+        let mut outcome = PushOutcome::Queued;
+        if inner.items.len() >= self.capacity {
+            match self.policy {
+                DropPolicy::DropOldest => {
+                    inner.items.pop_front();
+                    outcome = PushOutcome::DroppedOldest;
+                }
+                DropPolicy::DropNewest => return PushOutcome::DroppedNewest,
+                DropPolicy::Block => {
+                    while inner.items.len() >= self.capacity && !inner.closed {
+                        inner = self.not_full.wait(inner).unwrap();
+                    }
+                }
+            }
+        }
+        if inner.closed {
+            return PushOutcome::Closed;
+        }
+        inner.items.push_back(item);
+        drop(inner);
+        self.not_empty.notify_one();
+        outcome
+    }
+
+    // Waits for the next item. Returns None once the queue is closed and drained.
+    pub async fn pop(&self) -> Option<T> {
+        loop {
+            {
+                let mut inner = self.inner.lock().unwrap();
+                if let Some(item) = inner.items.pop_front() {
+                    self.not_full.notify_one();
+                    return Some(item);
+                }
+                if inner.closed {
+                    return None;
+                }
+            }
+            self.not_empty.notified().await;
+        }
+    }
+
+    // Wakes up both sides; queued items can still be popped.
+    pub fn close(&self) {
+        self.inner.lock().unwrap().closed = true;
+        self.not_full.notify_all();
+        self.not_empty.notify_one();
+    }
+
+    // Queues a last item regardless of the policy, evicting the oldest one
+    // if the queue is full, then closes the queue.
+    pub fn finish(&self, item: T) {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.items.len() >= self.capacity {
+                inner.items.pop_front();
+            }
+            inner.items.push_back(item);
+        }
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    async fn drain<T>(queue: &FrameQueue<T>) -> Vec<T> {
+        let mut items = Vec::new();
+        while let Some(item) = queue.pop().await {
+            items.push(item);
+        }
+        items
+    }
+
+    // Pushes from another thread, as the capture worker does, and reports the outcome.
+    fn push_in_thread(queue: &Arc<FrameQueue<u32>>, item: u32) -> mpsc::Receiver<PushOutcome> {
+        let (tx, rx) = mpsc::channel();
+        let queue = queue.clone();
+        thread::spawn(move || tx.send(queue.push(item)).unwrap());
+        rx
+    }
+
+    #[tokio::test]
+    async fn capacity_is_not_preallocated() {
+        let queue = FrameQueue::new(usize::MAX, DropPolicy::DropOldest);
+        assert_eq!(queue.push(1), PushOutcome::Queued);
+        queue.close();
+        assert_eq!(drain(&queue).await, vec![1]);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_keeps_the_newest_frames() {
+        let queue = FrameQueue::new(2, DropPolicy::DropOldest);
+        assert_eq!(queue.push(1), PushOutcome::Queued);
+        assert_eq!(queue.push(2), PushOutcome::Queued);
+        assert_eq!(queue.push(3), PushOutcome::DroppedOldest);
+        queue.close();
+        assert_eq!(drain(&queue).await, vec![2, 3]);
+    }
+
+    #[tokio::test]
+    async fn drop_newest_keeps_the_oldest_frames() {
+        let queue = FrameQueue::new(2, DropPolicy::DropNewest);
+        assert_eq!(queue.push(1), PushOutcome::Queued);
+        assert_eq!(queue.push(2), PushOutcome::Queued);
+        assert_eq!(queue.push(3), PushOutcome::DroppedNewest);
+        queue.close();
+        assert_eq!(drain(&queue).await, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn block_waits_for_the_publisher() {
+        let queue = Arc::new(FrameQueue::new(1, DropPolicy::Block));
+        assert_eq!(queue.push(1), PushOutcome::Queued);
+        let pushed = push_in_thread(&queue, 2);
+        assert!(pushed.recv_timeout(Duration::from_millis(100)).is_err());
+        assert_eq!(queue.pop().await, Some(1));
+        assert_eq!(
+            pushed.recv_timeout(Duration::from_secs(5)),
+            Ok(PushOutcome::Queued)
+        );
+        assert_eq!(queue.pop().await, Some(2));
+    }
+
+    #[tokio::test]
+    async fn close_releases_a_blocked_producer() {
+        let queue = Arc::new(FrameQueue::new(1, DropPolicy::Block));
+        assert_eq!(queue.push(1), PushOutcome::Queued);
+        let pushed = push_in_thread(&queue, 2);
+        assert!(pushed.recv_timeout(Duration::from_millis(100)).is_err());
+        queue.close();
+        assert_eq!(
+            pushed.recv_timeout(Duration::from_secs(5)),
+            Ok(PushOutcome::Closed)
+        );
+        // Frames queued before close are still delivered.
+        assert_eq!(drain(&queue).await, vec![1]);
+    }
+
+    #[tokio::test]
+    async fn close_wakes_a_waiting_publisher() {
+        let queue = Arc::new(FrameQueue::<u32>::new(2, DropPolicy::DropOldest));
+        let publisher = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.pop().await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        queue.close();
+        let popped = tokio::time::timeout(Duration::from_secs(5), publisher).await;
+        assert_eq!(popped.unwrap().unwrap(), None);
+        assert_eq!(queue.push(1), PushOutcome::Closed);
+    }
+
+    #[tokio::test]
+    async fn push_after_close_keeps_the_queued_frames() {
+        let queue = FrameQueue::new(2, DropPolicy::DropOldest);
+        queue.push(1);
+        queue.push(2);
+        queue.close();
+        assert_eq!(queue.push(3), PushOutcome::Closed);
+        assert_eq!(drain(&queue).await, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn finish_queues_a_last_frame_and_closes() {
+        let queue = FrameQueue::new(2, DropPolicy::DropNewest);
+        queue.push(1);
+        queue.push(2);
+        queue.finish(3);
+        assert_eq!(queue.push(4), PushOutcome::Closed);
+        assert_eq!(drain(&queue).await, vec![2, 3]);
+    }
+}
@@ -17,166 +17,480 @@
 ///
 ///
 ///
-use camera_driver::interface;
-use camera_driver::interface::CameraInterface;
-use camera_driver::mock::MockCamera;
-use camera_driver::svb_camera;
-use camera_driver::svb_camera::SVBCameraWrapper;
-use env_logger;
-use serde_json::error;
-use std::hash::Hash;
-use std::time::Instant;
+use camera_driver::backend::BackendRegistry;
+use camera_driver::interface::{CameraError, CameraInterface, CameraResult, DeviceDescriptor};
+use camera_driver::mock::MockBackend;
+use protocol::{
+    AppliedCtrlValue, CmdError, Command, CtrlValue, DeviceChange, DeviceFailure, FrameData, InitData,
+    Rejected, Request, Response,
+};
+use base64::{engine::general_purpose, Engine as _};
+use capture::{CaptureEvent, CaptureWorker};
+use frame::FrameEncoding;
+use queue::FrameQueue;
+use status::{CameraStats, CameraStatus};
+use config::{CamerasConfig, Config};
+use inventory::{DeviceActivity, Inventory, InventoryEntry};
+use presence::Presence;
+use topics::{ReplyTopics, Route, Topics};
+use transport::{Backoff, Client, Notification, Will};
 
 use log::{debug, error, info, warn};
-use rumqttc::{self, AsyncClient, Event, MqttOptions, QoS};
-use serde::{de, Deserialize, Serialize};
-use std::collections::HashMap;
+use bytes::Bytes;
+use rumqttc::{self, QoS};
 use std::sync::Arc;
+use std::sync::Mutex as StdMutex;
 use std::time::Duration;
 use tokio::sync::Mutex;
 use tokio::task;
 
-const ResponceTopic: &str = "camera/responce";
-const InitTopic: &str = "camera/init";
+mod capture;
+mod config;
+mod frame;
+mod inventory;
+mod presence;
+mod protocol;
+mod queue;
+mod status;
+mod topics;
+mod transport;
 
-#[derive(Debug, Clone)]
-pub enum Vendor {
-    MOCK(Arc<Mutex<MockCamera>>),
-    SVBONY(Arc<Mutex<SVBCameraWrapper>>),
+const MQTT_REQUEST_CAPACITY: usize = 16;
+
+// An opened camera of any backend, shared by the command handlers and its capture worker.
+type Camera = Arc<Mutex<Box<dyn CameraInterface + Send>>>;
+
+// What the server keeps for an opened camera besides the camera itself.
+#[derive(Debug)]
+pub struct DeviceState {
+    // Stable camera id (see CamerasConfig::camera_id).
+    id: String,
+    stats: Arc<StdMutex<CameraStats>>,
+    capture: Mutex<Option<CaptureWorker>>,
 }
-#[derive(Debug, PartialEq, Eq)]
-pub enum CameraCmd {
-    GetInfo = 0,
-    GetStatus,
-    GetRoi,
-    GetCtrlVal,
-    SetRoi,
-    SetCtrlVal,
-    StartCapture,
-    StopCapture,
-    Init,
-    AdjustWB,
-    NotImplemented = -1,
+
+#[derive(Clone)]
+pub struct Device {
+    camera: Camera,
+    // Name of the backend that opened the camera.
+    backend: &'static str,
+    // How the backend listed the camera when it was opened, the serial number
+    // identifies it across rescans.
+    descriptor: DeviceDescriptor,
+    state: Arc<DeviceState>,
 }
-impl CameraCmd {
-    fn from_i32(cmd_idx: &i32) -> CameraCmd {
-        let cmd = match cmd_idx {
-            0 => CameraCmd::GetInfo,
-            1 => CameraCmd::GetStatus,
-            2 => CameraCmd::GetRoi,
-            3 => CameraCmd::GetCtrlVal,
-            4 => CameraCmd::SetRoi,
-            5 => CameraCmd::SetCtrlVal,
-            6 => CameraCmd::StartCapture,
-            7 => CameraCmd::StopCapture,
-            8 => CameraCmd::Init,
-            9 => CameraCmd::AdjustWB,
-            _ => {
-                error!("Unknown Payload value");
-                CameraCmd::NotImplemented
-            }
-        };
-        cmd
+impl Device {
+    fn new(
+        camera: Box<dyn CameraInterface + Send>,
+        backend: &'static str,
+        descriptor: DeviceDescriptor,
+        id: String,
+    ) -> Self {
+        Self {
+            camera: Arc::new(Mutex::new(camera)),
+            backend,
+            descriptor,
+            state: Arc::new(DeviceState {
+                id,
+                stats: Arc::new(StdMutex::new(CameraStats::new())),
+                capture: Mutex::new(None),
+            }),
+        }
+    }
+    fn is(&self, backend: &str, descriptor: &DeviceDescriptor) -> bool {
+        self.backend == backend && self.descriptor.serial == descriptor.serial
+    }
+    fn change(&self) -> DeviceChange {
+        DeviceChange {
+            id: self.state.id.clone(),
+            vendor: self.backend.to_string(),
+            name: self.descriptor.name.clone(),
+            serial: self.descriptor.serial.clone(),
+        }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Payload {
-    transaction_id: String,
-    camera_idx: i32,
-    cmd_idx: i32,
-    data: HashMap<String, String>,
-}
-
-fn get_devices() -> Vec<Vendor> {
-    let mut devices = Vec::new();
-    let num_mock = MockCamera::num_devices();
-    if num_mock > 0 {
-        for i in 0..num_mock {
-            devices.push(Vendor::MOCK(Arc::new(Mutex::new(MockCamera::new(i)))));
+// Brings the device list in line with the connected cameras : cameras that are
+// still connected are left untouched (a running capture keeps going), cameras
+// that disappeared are closed and removed, new cameras are opened and appended.
+// Backends are scanned in registration order. The SDK calls run on the blocking
+// thread pool. Cameras that failed to open are retried by the next rescan.
+async fn rescan_devices(
+    devices: &mut Vec<Device>,
+    registry: &Arc<BackendRegistry>,
+    cameras: &CamerasConfig,
+) -> InitData {
+    let mut found = Vec::new();
+    // Backends whose enumeration failed keep their cameras until the next rescan.
+    let mut unavailable = Vec::new();
+    for name in registry.names() {
+        if !cameras.is_enabled(name) {
+            continue;
+        }
+        let registry_1 = registry.clone();
+        let listed = blocking(move || match registry_1.get(name) {
+            Some(backend) => backend.enumerate(),
+            None => Ok(Vec::new()),
+        })
+        .await;
+        match listed {
+            Ok(descriptors) => found.extend(descriptors.into_iter().map(|d| (name, d))),
+            Err(e) => {
+                error!("[ MQTTServer ] : Failed to list {} cameras : {}", name, e);
+                unavailable.push(name);
+            }
         }
     }
 
-    let num_svb = SVBCameraWrapper::num_devices();
-    if num_svb > 0 {
-        for i in 0..num_svb {
-            devices.push(Vendor::SVBONY(Arc::new(Mutex::new(SVBCameraWrapper::new(
-                i as usize,
-            )))));
+    let mut removed = Vec::new();
+    let mut kept = Vec::new();
+    for device in devices.drain(..) {
+        let connected = unavailable.contains(&device.backend)
+            || found.iter().any(|(vendor, d)| device.is(vendor, d));
+        if connected {
+            kept.push(device);
+        } else {
+            info!("[ MQTTServer ] : Camera {} is disconnected", device.state.id);
+            close_device(&device).await;
+            removed.push(device.change());
         }
     }
-    devices
-}
-async fn close_devices(devices: &Vec<Vendor>) {
-    for device in devices {
-        match device {
-            Vendor::MOCK(mock) => {
-                mock.lock().await.close();
+    *devices = kept;
+
+    let mut added = Vec::new();
+    let mut failed = Vec::new();
+    for (vendor, descriptor) in found {
+        if devices.iter().any(|device| device.is(vendor, &descriptor)) {
+            continue;
+        }
+        let id = cameras.camera_id(vendor, &descriptor.serial);
+        let registry_1 = registry.clone();
+        let descriptor_1 = descriptor.clone();
+        let opened = blocking(move || match registry_1.get(vendor) {
+            Some(backend) => backend.open(&descriptor_1),
+            None => Err(CameraError::DeviceNotFound(descriptor_1.idx)),
+        })
+        .await;
+        match opened {
+            Ok(camera) => {
+                let device = Device::new(camera, vendor, descriptor, id);
+                added.push(device.change());
+                devices.push(device);
             }
-            Vendor::SVBONY(svb) => {
-                svb.lock().await.close();
+            Err(e) => {
+                error!(
+                    "[ MQTTServer ] : Failed to open {} camera {} : {}",
+                    vendor, descriptor.idx, e
+                );
+                failed.push(DeviceFailure {
+                    device: DeviceChange {
+                        id,
+                        vendor: vendor.to_string(),
+                        name: descriptor.name,
+                        serial: descriptor.serial,
+                    },
+                    error: e.to_string(),
+                });
             }
         }
     }
+    InitData {
+        num_device: devices.len(),
+        added,
+        removed,
+        failed,
+    }
+}
+// Runs a blocking SDK call on the blocking thread pool, a panic is resumed in the caller.
+async fn blocking<T, F>(f: F) -> CameraResult<T>
+where
+    F: FnOnce() -> CameraResult<T> + Send + 'static,
+    T: Send + 'static,
+{
+    match task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+// Reads the status of the camera off the async runtime, the SDK calls may block.
+async fn collect_status<T: CameraInterface + Send + 'static>(
+    camera: &Arc<Mutex<T>>,
+    stats: &Arc<StdMutex<CameraStats>>,
+) -> CameraResult<CameraStatus> {
+    let camera = camera.clone();
+    let stats = stats.clone();
+    blocking(move || {
+        let camera = camera.blocking_lock();
+        let stats = stats.lock().unwrap();
+        Ok(CameraStatus::collect(&*camera, &stats))
+    })
+    .await
+}
+async fn collect_inventory(devices: &[Device]) -> Inventory {
+    let mut cameras = Vec::new();
+    for (idx, device) in devices.iter().enumerate() {
+        let info = device.camera.lock().await.get_info();
+        let capturing = device
+            .state
+            .capture
+            .lock()
+            .await
+            .as_ref()
+            .is_some_and(|worker| worker.is_running());
+        cameras.push(InventoryEntry {
+            id: device.state.id.clone(),
+            idx,
+            vendor: device.backend.to_string(),
+            serial: device.descriptor.serial.clone(),
+            info,
+            state: if capturing {
+                DeviceActivity::Capturing
+            } else {
+                DeviceActivity::Idle
+            },
+        });
+    }
+    Inventory::new(cameras)
+}
+// Camera selected by a request : by id when camera_id is given (the legacy
+// index is accepted there too), by position otherwise.
+fn find_device<'a>(
+    devices: &'a [Device],
+    camera_id: Option<&str>,
+    camera_idx: i32,
+) -> Option<(usize, &'a Device)> {
+    let idx = match camera_id {
+        Some(id) => match devices.iter().position(|device| device.state.id == id) {
+            Some(idx) => idx,
+            None => id.parse().ok()?,
+        },
+        None => usize::try_from(camera_idx).ok()?,
+    };
+    devices.get(idx).map(|device| (idx, device))
+}
+async fn close_device(device: &Device) {
+    if let Some(worker) = device.state.capture.lock().await.take() {
+        worker.stop().await;
+    }
+    let camera = device.camera.clone();
+    if let Err(e) = blocking(move || camera.blocking_lock().close()).await {
+        error!("[ MQTTServer ] : Failed to close camera : {}", e);
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct MQTTCameraServer {
-    client: AsyncClient,
+    client: Client,
+    config: Arc<Config>,
+    topics: Arc<Topics>,
+    // Locked by the tasks handling the requests, never by the event loop : a rescan
+    // holds it while cameras are opened.
+    devices: Arc<Mutex<Vec<Device>>>,
 }
 impl MQTTCameraServer {
-    fn new(client: AsyncClient) -> Self {
-        Self { client }
-    }
-    fn gen_responce(
-        &self,
-        t_id: &String,
-        camera_idx: &i32,
-        cmd_idx: &i32,
-        data: String,
-    ) -> Result<String, serde_json::Error> {
-        let mut res = HashMap::new();
-        res.insert("transaction_id", t_id.to_string());
-        res.insert("camera_idx", camera_idx.to_string());
-        res.insert("cmd_idx", cmd_idx.to_string());
-        res.insert("data", data);
-        let res_json = serde_json::to_string(&res);
-        res_json
-    }
-    fn to_json(&self, responce: &HashMap<String, String>) -> Result<String, serde_json::Error> {
-        let res_json = serde_json::to_string(&responce);
-        res_json
+    fn new(client: Client, config: Arc<Config>) -> Self {
+        let topics = Topics::new(&config.topics.prefix, config.topics.legacy);
+        Self {
+            client,
+            config,
+            topics: Arc::new(topics),
+            devices: Arc::new(Mutex::new(Vec::new())),
+        }
     }
     // Subscribes to the topic
     async fn subscribe(&self, topics: &str) {
         self.client
-            .subscribe(topics, QoS::ExactlyOnce)
+            .subscribe(topics, self.config.qos.command())
             .await
             .unwrap_or_else(|e| {
                 error!("Error subscribing to topics: {:?}", e);
             });
     }
-    // Publishes a message to the topic
-    async fn publish(&self, topic: &str, payload: &str) {
+    // Publishes a message that is kept by the broker for late subscribers
+    async fn publish_retained(&self, topic: &str, payload: &str) {
+        let qos = self.config.qos.status();
+        self.send(topic, qos, true, payload.as_bytes().to_vec(), None)
+            .await;
+    }
+    // Publishes a binary frame to the topic
+    async fn publish_bytes(&self, topic: &str, payload: Vec<u8>, correlation_data: Option<Bytes>) {
+        let qos = self.config.qos.frame();
+        self.send(topic, qos, false, payload, correlation_data)
+            .await;
+    }
+    async fn send(
+        &self,
+        topic: &str,
+        qos: QoS,
+        retain: bool,
+        payload: Vec<u8>,
+        correlation_data: Option<Bytes>,
+    ) {
         self.client
-            .publish(topic, QoS::AtLeastOnce, false, payload)
+            .publish(topic, qos, retain, payload, correlation_data)
             .await
             .unwrap_or_else(|e| {
                 error!("Error publishing message: {:?}", e);
             });
     }
+    // Wraps the command result in the responce envelope and publishes it.
+    async fn publish_reply(
+        &self,
+        to: &ReplyTopics,
+        req: &Request,
+        result: Result<&Response, &CmdError>,
+    ) {
+        if let Err(e) = result {
+            error!(
+                "[ MQTTServer ] : {} failed on camera_idx = {:?} : {}",
+                req.command.name(),
+                req.camera_idx,
+                e
+            );
+        }
+        match req.reply(result) {
+            Ok(res) => {
+                let qos = self.config.qos.response();
+                self.send(&to.response, qos, false, res.into_bytes(), to.correlation_data.clone())
+                    .await
+            }
+            Err(e) => error!("[ MQTTServer ] : Failed to encode responce : {:?}", e),
+        }
+    }
+    // Publishes the error response for a message that could not be decoded.
+    async fn publish_rejected(&self, to: &ReplyTopics, rejected: &Rejected) {
+        match rejected.reply() {
+            Ok(res) => {
+                let qos = self.config.qos.response();
+                self.send(&to.response, qos, false, res.into_bytes(), to.correlation_data.clone())
+                    .await
+            }
+            Err(e) => error!("[ MQTTServer ] : Failed to encode responce : {:?}", e),
+        }
+    }
+    // Publishes the retained online presence of the server.
+    async fn publish_presence(&self, num_device: usize) {
+        let presence = Presence::online(&self.config.broker.client_id, num_device);
+        match serde_json::to_string(&presence) {
+            Ok(presence) => {
+                self.publish_retained(&self.topics.presence_topic(), &presence)
+                    .await
+            }
+            Err(e) => error!("[ MQTTServer ] : Failed to encode presence : {:?}", e),
+        }
+    }
+    // Publishes the retained list of opened cameras.
+    async fn publish_inventory(&self, inventory: &Inventory) {
+        match serde_json::to_string(inventory) {
+            Ok(inventory) => {
+                self.publish_retained(&self.topics.inventory_topic(), &inventory)
+                    .await
+            }
+            Err(e) => error!("[ MQTTServer ] : Failed to encode inventory : {:?}", e),
+        }
+    }
+    // Publishes the inventory again after a camera started or stopped capturing.
+    async fn republish_inventory(&self) {
+        let inventory = collect_inventory(&self.devices.lock().await).await;
+        self.publish_inventory(&inventory).await;
+    }
+    // Publishes the current status of the camera on its retained status topic.
+    async fn publish_status<T: CameraInterface + Send + 'static>(
+        &self,
+        camera_id: &str,
+        camera: &Arc<Mutex<T>>,
+        stats: &Arc<StdMutex<CameraStats>>,
+    ) {
+        let status = match collect_status(camera, stats).await {
+            Ok(status) => status,
+            Err(e) => {
+                error!("[ MQTTServer ] : Failed to read status : {}", e);
+                return;
+            }
+        };
+        match serde_json::to_string(&status) {
+            Ok(status) => {
+                self.publish_retained(&self.topics.status_topic(camera_id), &status)
+                    .await
+            }
+            Err(e) => error!("[ MQTTServer ] : Failed to encode status : {:?}", e),
+        }
+    }
 
-    // The process is executed according to the command index extracted from the payload.
-    pub async fn cmd_process<T: CameraInterface>(&mut self, camera: Arc<Mutex<T>>, dict: Payload) {
-        let transaction_id = dict.transaction_id;
-        let camera_idx = dict.camera_idx;
-        let cmd_idx = dict.cmd_idx;
-        let mut data = dict.data;
+    // The process is executed according to the command extracted from the payload.
+    pub async fn cmd_process<T: CameraInterface + Send + 'static>(
+        &mut self,
+        camera: Arc<Mutex<T>>,
+        state: Arc<DeviceState>,
+        req: Request,
+        to: ReplyTopics,
+    ) {
+        let result = self.exec_cmd(camera.clone(), &state, &req, &to).await;
+        if let Err(e) = &result {
+            state.stats.lock().unwrap().record_error(e);
+        }
+        self.publish_reply(&to, &req, result.as_ref()).await;
+        if result.is_ok() && req.command.changes_state() {
+            self.publish_status(&state.id, &camera, &state.stats)
+                .await;
+        }
+        if result.is_ok() && req.command.changes_activity() {
+            self.republish_inventory().await;
+        }
+    }
 
-        let res_data = match CameraCmd::from_i32(&cmd_idx) {
-            CameraCmd::GetInfo => {
+    // Publishes the frames produced by a capture worker until it stops.
+    async fn publish_frames<T: CameraInterface + Send + 'static>(
+        self,
+        camera: Arc<Mutex<T>>,
+        state: Arc<DeviceState>,
+        req: Request,
+        to: ReplyTopics,
+        queue: Arc<FrameQueue<CaptureEvent>>,
+        encoding: FrameEncoding,
+    ) {
+        let camera_idx = req.camera_idx as u16;
+        while let Some(event) = queue.pop().await {
+            match event {
+                CaptureEvent::Frame(frame) => match encoding {
+                    FrameEncoding::Binary => match frame.encode(camera_idx) {
+                        Ok(payload) => {
+                            self.publish_bytes(&to.frame, payload, to.correlation_data.clone())
+                                .await
+                        }
+                        Err(e) => error!("[ MQTTServer ] : Failed to encode frame : {:?}", e),
+                    },
+                    FrameEncoding::Base64 => {
+                        let res = Response::Frame(FrameData {
+                            frame: general_purpose::STANDARD.encode(&frame.frame.data),
+                            meta: frame.meta,
+                        });
+                        self.publish_reply(&to, &req, Ok(&res)).await;
+                    }
+                },
+                CaptureEvent::Error(e) => {
+                    self.publish_reply(&to, &req, Err(&e.into())).await;
+                    self.publish_status(&state.id, &camera, &state.stats).await;
+                    self.republish_inventory().await;
+                }
+            }
+        }
+    }
+
+    async fn exec_cmd<T: CameraInterface + Send + 'static>(
+        &mut self,
+        camera: Arc<Mutex<T>>,
+        state: &Arc<DeviceState>,
+        req: &Request,
+        to: &ReplyTopics,
+    ) -> Result<Response, CmdError> {
+        let stats = &state.stats;
+        let camera_idx = req.camera_idx;
+
+        let res_data = match &req.command {
+            Command::GetInfo => {
                 //
-                // incoming and outcoming data field  :
+                // outcoming data field  :
                 // {    name,
                 //      idx,
                 //      max_width,
@@ -187,40 +501,42 @@ impl MQTTCameraServer {
                 // }
 
                 let info = camera.lock().await.get_info();
-                let info_json = serde_json::to_string(&info);
                 info!(
                     "[ MQTTServer ] : GetInfo command is executed by camera_idx = {:?}",
                     camera_idx
                 );
-                info_json.unwrap()
+                Response::Info(info)
             }
-            CameraCmd::GetStatus => {
-                //let status = camera.get_status();
-                let status = r#"{"statuts"}"#;
-                let status_json = serde_json::to_string(&status);
-                status_json.unwrap()
+            Command::GetStatus => {
+                let status = collect_status(&camera, stats).await?;
+                info!(
+                    "[ MQTTServer ] : GetStatus command is executed by camera_idx = {:?}",
+                    camera_idx
+                );
+                Response::Status(status)
             }
-            CameraCmd::GetCtrlVal => {
-                // incoming and outcoming data field  :
+            Command::GetCtrlVal(args) => {
+                // outcoming data field  :
                 // {    ctrl_type
                 //    value
                 // }
 
-                let ctrl_type_idx: i32 = data.get("ctrl_type").unwrap().parse().unwrap();
-                let ctrl_type = interface::ControlType::from_i32(&ctrl_type_idx);
-                let val = camera.lock().await.get_control_value(ctrl_type);
-
-                data.insert("value".to_string(), val.to_string());
-                let val_json = serde_json::to_string(&data);
+                let ctrl_type = args.ctrl_type;
+                let camera = camera.clone();
+                let val = blocking(move || camera.blocking_lock().get_control_value(ctrl_type))
+                    .await?;
                 info!(
                     "[ MQTTServer ] GetCtrlVal command is executed by camera_idx = {:?}",
                     camera_idx
                 );
-                val_json.unwrap()
+                Response::CtrlVal(CtrlValue {
+                    ctrl_type: args.ctrl_type,
+                    value: val,
+                })
             }
-            CameraCmd::GetRoi => {
+            Command::GetRoi => {
                 //
-                // incoming and outcoming data field  :
+                // outcoming data field  :
                 // {    startx,
                 //      starty,
                 //      width,
@@ -229,146 +545,234 @@ impl MQTTCameraServer {
                 //      img_type
                 // }
                 let roi = camera.lock().await.get_roi();
-                let roi_json = serde_json::to_string(&roi).unwrap();
                 info!(
                     "[ MQTTServer ] : GetRoi command is executed by camera_idx = {:?}",
                     camera_idx
                 );
-                roi_json
+                Response::Roi(roi)
             }
-            CameraCmd::SetCtrlVal => {
+            Command::SetCtrlVal(args) => {
                 // Return ctrl value  after set control value
                 //
-                // incoming and outcoming data field  :
+                // The value is checked against the control capabilities and
+                // clamped to [min_value, max_value] before it is written.
+                //
+                // outcoming data field  :
                 // {
-                //      ctrl_type : int,
-                //      value : int
+                //      ctrl_type,
+                //      value,
+                //      requested,
+                //      applied,
+                //      clamped
                 // }
 
-                let ctrl_type_idx: i32 = data.get("ctrl_type").unwrap().parse().unwrap();
-                let ctrl_type = interface::ControlType::from_i32(&ctrl_type_idx);
-                let value: i64 = data.get("value").unwrap().parse().unwrap();
-                let is_auto =   data.get("is_auto").unwrap().parse().unwrap();
-              
-                camera
-                    .lock()
-                    .await
-                    .set_control_value(ctrl_type, value, is_auto);
+                let camera_1 = camera.clone();
+                let caps = blocking(move || camera_1.blocking_lock().get_control_caps())
+                    .await?
+                    .into_iter()
+                    .find(|caps| caps.control_type == args.ctrl_type)
+                    .ok_or_else(|| {
+                        CameraError::Unsupported(format!(
+                            "control {:?} is not available on this camera",
+                            args.ctrl_type
+                        ))
+                    })?;
+                if !caps.is_writable {
+                    return Err(CmdError::ReadOnlyControl(args.ctrl_type));
+                }
+                if args.is_auto && !caps.is_auto_supported {
+                    return Err(CmdError::AutoNotSupported(args.ctrl_type));
+                }
+                let applied = caps.clamp(args.value);
+                if applied != args.value {
+                    warn!(
+                        "[ MQTTServer ] : {:?} value {} clamped to {} on camera_idx = {:?}",
+                        args.ctrl_type, args.value, applied, camera_idx
+                    );
+                }
+
+                let (ctrl_type, is_auto) = (args.ctrl_type, args.is_auto as i64);
+                let camera = camera.clone();
+                let val = blocking(move || {
+                    let camera = camera.blocking_lock();
+                    camera.set_control_value(ctrl_type, applied, is_auto)?;
+                    camera.get_control_value(ctrl_type)
+                })
+                .await?;
                 info!(
                     "[ MQTTServer ] : SetCtrlVal command is executed by camera_idx = {:?}",
                     camera_idx
                 );
 
-                let val = camera.lock().await.get_control_value(ctrl_type);
-                let mut res = HashMap::new();
-                res.insert("value".to_string(), val.to_string());
-                res.insert("ctrl_type".to_string(), ctrl_type_idx.to_string());
-
-                let ctrl_json = serde_json::to_string(&res).unwrap();
-                ctrl_json
+                Response::AppliedCtrlVal(AppliedCtrlValue {
+                    ctrl_type: args.ctrl_type,
+                    value: val,
+                    requested: args.value,
+                    applied,
+                    clamped: applied != args.value,
+                })
             }
-            CameraCmd::SetRoi => {
-                // Return ROI after set ROI
+            Command::SetRoi(args) => {
+                // Return the effective ROI after set ROI.
+                // The request is validated against the sensor geometry and may be snapped
+                // to the SDK alignment (see camera_driver::roi::validate_roi).
                 // responce data field  :
-                // {    startx : int,
-                //      starty : int,
-                //      width : int ,
-                //      height : int,
-                //      bin : int,
-                //      img_type : int
+                // {    startx,
+                //      starty,
+                //      width,
+                //      height,
+                //      bin,
+                //      img_type
                 // }
 
-                let startx: u32 = data.get("startx").unwrap().parse().unwrap();
-                let starty: u32 = data.get("starty").unwrap().parse().unwrap();
-                let width: u32 = data.get("width").unwrap().parse().unwrap();
-                let height: u32 = data.get("height").unwrap().parse().unwrap();
-                let bin: u8 = data.get("bin").unwrap().parse().unwrap();
-                let img_type: i32 = data.get("img_type").unwrap().parse().unwrap();
-                let img_type = interface::ImgType::from_i32(&img_type);
-                camera
-                    .lock()
-                    .await
-                    .set_roi(startx, starty, width, height, bin, img_type);
+                let args = args.clone();
+                let camera = camera.clone();
+                let roi = blocking(move || {
+                    let mut camera = camera.blocking_lock();
+                    camera.set_roi(
+                        args.startx,
+                        args.starty,
+                        args.width,
+                        args.height,
+                        args.bin,
+                        args.img_type,
+                    )?;
+                    Ok(camera.get_roi())
+                })
+                .await?;
                 info!(
                     "[ MQTTServer ] : SetRoi command is executed by camera_idx = {:?}",
                     camera_idx
                 );
-                let roi = camera.lock().await.get_roi();
-                let roi_json = serde_json::to_string(&roi).unwrap();
-                roi_json
+                Response::Roi(roi)
             }
-            CameraCmd::StartCapture => {
+            Command::StartCapture(args) => {
                 //
-                // responce data field  :
+                // The camera starts capturing and a capture worker publishes the frame data
+                // until StopCapture command is executed.
+                // The command itself is answered as soon as the worker is running.
+                //
+                // encoding = "binary" (default of typed requests, see capture in the config) :
+                //   each frame is published on the frame topic as a binary header followed
+                //   by the frame metadata and the raw pixels (see frame.rs).
+                //
+                // encoding = "base64" (default of legacy requests) :
+                //   each frame is published on the response topic with the transaction_id of this command.
+                //   responce data field  :
+                //   {
+                //       frame : base64 encoded raw data,
+                //       meta : { camera_id, seq, timestamp, exposure, gain, temperature, roi, img_type }
+                //   }
+                //
+                // optional incoming data field  :
                 // {
-                //       frame : base64 encoded raw data
+                //       drop_policy : "drop_oldest" | "drop_newest" | "block",
+                //       queue_capacity : int,
+                //       encoding : "binary" | "base64"
                 // }
+                // Missing fields are taken from the capture section of the config.
+                // queue_capacity is limited to capture.max_queue_capacity of the config.
+                // Frames the publisher cannot keep up with are handled by drop_policy
+                // and counted in the frames_dropped field of GetStatus.
                 //
-                // The camera starts capturing and returns the frame data.
-                // The frame data is encoded in base64
-                // keep to catpure and publish frame data until StopCapture command is executed.
-                //
-                camera.lock().await.start_capture();
-                camera.lock().await.set_is_capture(true);
+                let args = args.clone().unwrap_or_default();
+                let defaults = &self.config.capture;
+                let queue_capacity = args.queue_capacity.unwrap_or(defaults.queue_capacity);
+                if queue_capacity > defaults.max_queue_capacity {
+                    return Err(CmdError::InvalidValue {
+                        field: "queue_capacity".to_string(),
+                        value: queue_capacity.to_string(),
+                    });
+                }
+
+                let mut capture = state.capture.lock().await;
+                if capture.as_ref().is_some_and(|worker| worker.is_running()) {
+                    return Err(CmdError::AlreadyCapturing);
+                }
+                let camera_1 = camera.clone();
+                blocking(move || {
+                    let mut camera = camera_1.blocking_lock();
+                    camera.start_capture()?;
+                    camera.set_is_capture(true);
+                    Ok(())
+                })
+                .await?;
+
+                let queue = Arc::new(FrameQueue::new(
+                    queue_capacity,
+                    args.drop_policy.unwrap_or(defaults.drop_policy),
+                ));
+                *capture = Some(CaptureWorker::spawn(
+                    camera.clone(),
+                    state.id.clone(),
+                    stats.clone(),
+                    queue.clone(),
+                ));
+                tokio::spawn(self.clone().publish_frames(
+                    camera.clone(),
+                    state.clone(),
+                    req.clone(),
+                    to.clone(),
+                    queue,
+                    args.encoding.unwrap_or(defaults.encoding),
+                ));
                 info!(
                     "[ MQTTServer ] : StartCapture command is executed by camera_idx = {:?}",
                     camera_idx
                 );
-                while camera.lock().await.is_capture() {
-                    let buf = camera.lock().await.get_frame();
-                    let start = Instant::now();
-                    let mut res = HashMap::new();
-                    res.insert("frame", buf);
-                    let buf_json = serde_json::to_string(&res).unwrap();
-
-                    let res: String = self
-                        .gen_responce(&transaction_id, &camera_idx, &cmd_idx, buf_json)
-                        .unwrap();
-                    self.publish(ResponceTopic, &res).await;
-
-                    let end = Instant::now();
-                    let elapsed = end.duration_since(start);
-                    //debug!("Get frame time = {:?}", elapsed);
-                }
-                r#"{}"#.to_string()
+                Response::Empty {}
             }
-            CameraCmd::StopCapture => {
+            Command::StopCapture => {
                 //
-                //  camera stop capturing and set is_capture = false
+                //  stop the capture worker, then camera stop capturing and set is_capture = false
 
-                camera.lock().await.set_is_capture(false);
-                camera.lock().await.stop_capture();
+                if let Some(worker) = state.capture.lock().await.take() {
+                    worker.stop().await;
+                }
+                let camera = camera.clone();
+                blocking(move || {
+                    let mut camera = camera.blocking_lock();
+                    camera.set_is_capture(false);
+                    camera.stop_capture()
+                })
+                .await?;
                 info!(
                     "[ MQTTServer ] : StopCapture command is executed by camera_idx = {:?}",
                     camera_idx
                 );
-                r#"{}"#.to_string()
+                Response::Empty {}
             }
-
-            CameraCmd::NotImplemented => {
-                error!(
-                    "[ MQTTServer ] : NotImplemented command is executed by camera_idx = {:?}",
+            Command::GetControlCaps => {
+                //
+                // outcoming data field  :
+                // [    {   name,
+                //          max_value,
+                //          min_value,
+                //          default_value,
+                //          is_auto_supported,
+                //          is_writable,
+                //          control_type
+                //      }, ...
+                // ]
+                let camera = camera.clone();
+                let caps = blocking(move || camera.blocking_lock().get_control_caps()).await?;
+                info!(
+                    "[ MQTTServer ] : GetControlCaps command is executed by camera_idx = {:?}",
                     camera_idx
                 );
-                r#"{}"#.to_string()
+                Response::ControlCaps(caps)
             }
-            CameraCmd::AdjustWB  => {
-                camera.lock().await.adjust_white_balance();
-                r#"{}"#.to_string()
+            Command::AdjustWB => {
+                let camera = camera.clone();
+                blocking(move || camera.blocking_lock().adjust_white_balance()).await?;
+                Response::Empty {}
             }
-            _ => {
-                error!(
-                    "[ MQTTServer ] : Unknown command or not using command is executed by camera_idx = {:?}",
-                    camera_idx
-                );
-                r#"{}"#.to_string()
+            Command::Init => {
+                // Init is handled on the init topic, not per camera.
+                return Err(CmdError::UnknownCommand(req.command.legacy_idx()));
             }
         };
-
-        let res: String = self
-            .gen_responce(&transaction_id, &camera_idx, &cmd_idx, res_data)
-            .unwrap();
-        self.publish(ResponceTopic, &res).await;
+        Ok(res_data)
     }
 }
 #[tokio::main(worker_threads = 10)]
@@ -376,88 +780,222 @@ async fn main() {
     //-> Result<(), Box<dyn Error>> {
     env_logger::init();
 
+    let config = match Config::load() {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            eprintln!("[ MQTTServer] {}", e);
+            std::process::exit(2);
+        }
+    };
+    println!("[ MQTTServer] Effective configuration :\n{}", config.display());
+
     // The mqtt server is established.
-    let mut mqttoptions = MqttOptions::new("mqtt-server", "localhost", 1883);
-    mqttoptions.set_keep_alive(Duration::from_secs(20));
-    mqttoptions.set_max_packet_size(100000000000, 1000000000000);
-    let (client, mut eventloop) = AsyncClient::new(mqttoptions.clone(), 100000000000);
-    let cli = MQTTCameraServer::new(client);
-    let cli_1 = cli.clone();
-
-    // Get all connected cameras.
-    //let mut devices = get_devices();
-    let mut devices: Vec<Vendor> = Vec::new();
-
-    task::spawn(async move {
-        cli_1.subscribe("camera/instr").await;
-        cli_1.subscribe("camera/init").await;
-    })
-    .await
-    .unwrap();
-
-    // This mqtt server receives messages from the mqtt client, and the camera executes the process according to the command index extracted in the payload.
-    while let Ok(event) = eventloop.poll().await {
-        match event {
-            Event::Incoming(pkt) => match pkt {
-                rumqttc::Packet::Publish(pkt) => {
-                    let topic = pkt.topic.as_str();
-                    let payload = std::str::from_utf8(&pkt.payload).unwrap().to_owned();
-                    let dict: Payload = serde_json::from_str(&payload).unwrap();
-                    let camera_idx = dict.camera_idx;
-                    let cmd_idx = dict.cmd_idx;
-                    let t_id = &dict.transaction_id;
-
-                    info!("[ MQTTServer] ====== Received Payload =======");
-                    info!("[ MQTTServer] Topic:            {}", topic);
-                    info!("[ MQTTServer] Camera index:     {}", camera_idx);
-                    info!("[ MQTTServer] Command received: {}", cmd_idx);
-                    info!("[ MQTTServer] Data received:    {:?}", dict.data);
-
-                    match topic {
-                        // init topic is get number of connected camera
-                        "camera/init" => {
-                            debug!("[ MQTTServer ] : Init publish to {} ", InitTopic);
-                            close_devices(&devices).await;
-                            devices = get_devices();
-
-                            let mut data = HashMap::new();
-                            data.insert("num_device".to_string(), devices.len().to_string());
-                            let data = cli.to_json(&data).unwrap();
-                            let res_json = cli.gen_responce(t_id, &-1, &8, data).unwrap();
-                            cli.publish(ResponceTopic, &res_json).await;
-                        }
-                        // instr topic is get camera command and execute command
-                        "camera/instr" => {
-                            let camera = devices[camera_idx as usize].clone();
-                            let mut cli_cln = cli.clone();
-
-                            // The process is executed asynchronously by the tokio library.
-                            tokio::spawn(async move {
-                                match camera {
-                                    Vendor::SVBONY(ref svb) => {
-                                        let svb = svb.clone();
-                                        cli_cln.cmd_process(svb, dict).await;
-                                    }
-                                    Vendor::MOCK(ref mock) => {
-                                        let mock = mock.clone();
-                                        cli_cln.cmd_process(mock, dict).await;
-                                    }
-                                    _ => error!("[ MQTTServer] Unknown camera vendor"),
-                                };
-                            });
-                        }
-                        _ => {
-                            error!("[ MQTTServer] Unknown topic");
-                        }
+    // The broker marks the server offline if the connection is lost.
+    let presence_topic = Topics::new(&config.topics.prefix, config.topics.legacy).presence_topic();
+    let will = Will {
+        topic: presence_topic,
+        payload: serde_json::to_vec(&Presence::offline(&config.broker.client_id))
+            .unwrap_or_default(),
+        qos: config.qos.status(),
+    };
+    // Keep the request queue short : frames are buffered (and dropped) by the
+    // per camera frame queue instead of piling up inside rumqttc.
+    let (client, mut eventloop) = match transport::connect(&config.broker, will, MQTT_REQUEST_CAPACITY) {
+        Ok(connection) => connection,
+        Err(e) => {
+            eprintln!("[ MQTTServer] {}", e);
+            std::process::exit(2);
+        }
+    };
+    let cli = MQTTCameraServer::new(client, config.clone());
+
+    // Cameras are opened by the backends of the registry at startup and on camera/init.
+    let mut registry = BackendRegistry::builtin();
+    registry.register(Box::new(MockBackend::new(config.cameras.mock.clone())));
+    let registry = Arc::new(registry);
+    let devices = cli.devices.clone();
+    // Open the connected cameras before the first connection, so that the presence
+    // and inventory published on connection list them without waiting for camera/init.
+    {
+        let mut devices = devices.lock().await;
+        let init = rescan_devices(&mut devices, &registry, &config.cameras).await;
+        info!(
+            "[ MQTTServer ] : Opened {} cameras at startup, failed to open {}",
+            init.num_device,
+            init.failed.len()
+        );
+    }
+
+    let mut backoff = Backoff::new(
+        Duration::from_secs(config.broker.reconnect_min_secs),
+        Duration::from_secs(config.broker.reconnect_max_secs),
+    );
+
+    // This mqtt server receives messages from the mqtt client, and the camera executes the process according to the command extracted in the payload.
+    // Connection errors are retried with backoff. Cameras and capture workers are kept
+    // meanwhile : frames that cannot be published are handled by the frame queue drop policy.
+    loop {
+        let pkt = match eventloop.poll().await {
+            Ok(Notification::Message(pkt)) => pkt,
+            Ok(Notification::Connected) => {
+                info!("[ MQTTServer] Connected to {}:{}", config.broker.host, config.broker.port);
+                backoff.reset();
+                // The subscriptions do not survive a clean session, renew them on every connection.
+                // Spawned because the requests are only sent while the event loop is polled.
+                let cli_1 = cli.clone();
+                let devices_1 = devices.clone();
+                task::spawn(async move {
+                    for topic in cli_1.topics.subscriptions() {
+                        cli_1.subscribe(&topic).await;
                     }
+                    let inventory = collect_inventory(&devices_1.lock().await).await;
+                    cli_1.publish_presence(inventory.num_device).await;
+                    cli_1.publish_inventory(&inventory).await;
+                });
+                continue;
+            }
+            Ok(Notification::Other) => continue,
+            Err(e) => {
+                let delay = backoff.next_delay();
+                error!(
+                    "[ MQTTServer] Connection error : {}, reconnecting in {:?}",
+                    e, delay
+                );
+                tokio::time::sleep(delay).await;
+                continue;
+            }
+        };
+        let topic = pkt.topic.as_str();
+        let route = match cli.topics.route(topic) {
+            Some(route) => route,
+            None => {
+                error!("[ MQTTServer] Unknown topic {}", topic);
+                continue;
+            }
+        };
+        let mut to = cli.topics.reply_topics(&route);
+        // MQTT v5 : answer on the response topic requested by the client.
+        if let Some(response_topic) = pkt.response_topic.clone() {
+            to.response = response_topic;
+        }
+        to.correlation_data = pkt.correlation_data.clone();
+        let mut req = match Request::decode(&pkt.payload) {
+            Ok(req) => req,
+            Err(mut rejected) => {
+                warn!("[ MQTTServer] Rejected payload on {} : {}", topic, rejected.error);
+                if let Route::Camera(camera) = &route {
+                    rejected.camera_id = Some(camera.clone());
                 }
-                _ => {
-                    debug!("[ MQTTServer] Other packet : {:?}", pkt);
+                let cli_1 = cli.clone();
+                task::spawn(async move { cli_1.publish_rejected(&to, &rejected).await });
+                continue;
+            }
+        };
+        // On a per camera topic the camera is given by the topic.
+        if let Route::Camera(camera) = &route {
+            req.camera_id = Some(camera.clone());
+        }
+
+        info!("[ MQTTServer] ====== Received Payload =======");
+        info!("[ MQTTServer] Topic:            {}", topic);
+        info!("[ MQTTServer] Camera id:        {:?}", req.camera_id);
+        info!("[ MQTTServer] Camera index:     {}", req.camera_idx);
+        info!("[ MQTTServer] Command received: {:?}", req.command);
+
+        match route {
+            // init topic is get number of connected camera
+            Route::Init => {
+                debug!("[ MQTTServer ] : Init publish to {} ", to.response);
+                // Spawned like the publications on connection : the rescan and the
+                // publications would otherwise stop the event loop from being polled.
+                let cli_1 = cli.clone();
+                let devices_1 = devices.clone();
+                let registry_1 = registry.clone();
+                task::spawn(async move {
+                    let mut devices = devices_1.lock().await;
+                    let init =
+                        rescan_devices(&mut devices, &registry_1, &cli_1.config.cameras).await;
+                    info!(
+                        "[ MQTTServer ] : Rescan added {}, removed {} and failed to open {} cameras",
+                        init.added.len(),
+                        init.removed.len(),
+                        init.failed.len()
+                    );
+                    // An empty retained message removes the status of the disconnected cameras.
+                    for change in &init.removed {
+                        cli_1.publish_retained(&cli_1.topics.status_topic(&change.id), "")
+                            .await;
+                    }
+
+                    req.camera_idx = -1;
+                    req.camera_id = None;
+                    req.command = Command::Init;
+                    let num_device = init.num_device;
+                    cli_1.publish_reply(&to, &req, Ok(&Response::Init(init))).await;
+                    cli_1.publish_presence(num_device).await;
+                    cli_1.publish_inventory(&collect_inventory(&devices).await)
+                        .await;
+                    for device in devices.iter() {
+                        cli_1
+                            .publish_status(&device.state.id, &device.camera, &device.state.stats)
+                            .await;
+                    }
+                });
+            }
+            // instr and per camera cmd topics get camera command and execute command
+            route @ (Route::Legacy | Route::Camera(_)) => {
+                // A request on camera/instr must name its camera, there is no default.
+                if !req.names_camera() {
+                    let cli_1 = cli.clone();
+                    task::spawn(async move {
+                        cli_1.publish_reply(&to, &req, Err(&CmdError::MissingCamera)).await
+                    });
+                    continue;
                 }
-            },
+                let mut cli_cln = cli.clone();
+                let devices_1 = devices.clone();
+                let has_response_topic = pkt.response_topic.is_some();
 
-            Event::Outgoing(v) => {
-                info!("[ MQTTServer] Outgoing = {:?}", v);
+                // The process is executed asynchronously by the tokio library.
+                tokio::spawn(async move {
+                    let mut to = to;
+                    // The device list is only locked for the lookup.
+                    let found = {
+                        let devices = devices_1.lock().await;
+                        find_device(&devices, req.camera_id.as_deref(), req.camera_idx)
+                            .map(|(idx, device)| (idx, device.clone()))
+                    };
+                    let device = match found {
+                        Some((idx, device)) => {
+                            req.camera_idx = idx as i32;
+                            req.camera_id = Some(device.state.id.clone());
+                            // A camera addressed by its index in the topic answers on the
+                            // topics of its id, like its status.
+                            if let Route::Camera(_) = route {
+                                let by_id = Route::Camera(device.state.id.clone());
+                                let topics = cli_cln.topics.reply_topics(&by_id);
+                                if !has_response_topic {
+                                    to.response = topics.response;
+                                }
+                                to.frame = topics.frame;
+                            }
+                            device
+                        }
+                        None => {
+                            let camera = req
+                                .camera_id
+                                .clone()
+                                .unwrap_or_else(|| req.camera_idx.to_string());
+                            cli_cln
+                                .publish_reply(&to, &req, Err(&CmdError::UnknownCamera(camera)))
+                                .await;
+                            return;
+                        }
+                    };
+                    cli_cln
+                        .cmd_process(device.camera, device.state, req, to)
+                        .await;
+                });
             }
         }
     }
@@ -0,0 +1,101 @@
+///
+/// Per camera bookkeeping reported by the GetStatus command.
+///
+use camera_driver::interface::{CameraInterface, ControlType, ImgType, ROIFormat};
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+// Counters kept by the server for one opened camera.
+#[derive(Debug, Clone)]
+pub struct CameraStats {
+    opened_at: Instant,
+    frames_captured: u64,
+    frames_failed: u64,
+    frames_dropped: u64,
+    last_error: Option<String>,
+}
+
+impl CameraStats {
+    pub fn new() -> Self {
+        Self {
+            opened_at: Instant::now(),
+            frames_captured: 0,
+            frames_failed: 0,
+            frames_dropped: 0,
+            last_error: None,
+        }
+    }
+    pub fn record_frame(&mut self) {
+        self.frames_captured += 1;
+    }
+    // get_frame timed out or failed.
+    pub fn record_failure(&mut self) {
+        self.frames_failed += 1;
+    }
+    // A captured frame was discarded because the frame queue was full.
+    pub fn record_drop(&mut self) {
+        self.frames_dropped += 1;
+    }
+    pub fn record_error(&mut self, err: impl ToString) {
+        self.last_error = Some(err.to_string());
+    }
+}
+
+//
+// data field of the GetStatus responce :
+// {    is_capture,
+//      roi,
+//      img_type,
+//      exposure,
+//      gain,
+//      temperature,        (null when the camera has no sensor temperature)
+//      cooler_power,       (null when the camera is not coolable)
+//      frames_captured,
+//      frames_failed,      (get_frame timed out or failed)
+//      frames_dropped,     (discarded because the frame queue was full)
+//      last_error,
+//      uptime_secs
+// }
+//
+// Control values are reported in the units of the vendor SDK.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CameraStatus {
+    pub is_capture: bool,
+    pub roi: ROIFormat,
+    pub img_type: Option<ImgType>,
+    pub exposure: Option<i64>,
+    pub gain: Option<i64>,
+    pub temperature: Option<i64>,
+    pub cooler_power: Option<i64>,
+    pub frames_captured: u64,
+    pub frames_failed: u64,
+    pub frames_dropped: u64,
+    pub last_error: Option<String>,
+    pub uptime_secs: u64,
+}
+
+impl CameraStatus {
+    pub fn collect<T: CameraInterface>(camera: &T, stats: &CameraStats) -> Self {
+        let cooler_power = if camera.get_info().is_coolable {
+            camera.get_control_value(ControlType::COOLER_POWER).ok()
+        } else {
+            None
+        };
+        CameraStatus {
+            is_capture: camera.is_capture(),
+            roi: camera.get_roi(),
+            img_type: camera.get_img_type().ok(),
+            exposure: camera.get_control_value(ControlType::EXPOSURE).ok(),
+            gain: camera.get_control_value(ControlType::GAIN).ok(),
+            temperature: camera
+                .get_control_value(ControlType::CURRENT_TEMPERATURE)
+                .ok(),
+            cooler_power,
+            frames_captured: stats.frames_captured,
+            frames_failed: stats.frames_failed,
+            frames_dropped: stats.frames_dropped,
+            last_error: stats.last_error.clone(),
+            uptime_secs: stats.opened_at.elapsed().as_secs(),
+        }
+    }
+}
@@ -0,0 +1,126 @@
+///
+/// MQTT topic layout.
+///
+/// per camera topics (`<prefix>` is topics.prefix in the config, "camera" by default,
+/// `<id>` is the stable camera id, the camera index is also accepted on the cmd topic,
+/// the answers and frames are then published on the topics of the id) :
+///   <prefix>/<id>/cmd         commands for one camera, camera_id / camera_idx in the payload are ignored
+///   <prefix>/<id>/response    answers to the commands received on <prefix>/<id>/cmd
+///   <prefix>/<id>/frame       binary frames of captures started on <prefix>/<id>/cmd
+///   <prefix>/<id>/status      retained CameraStatus, updated when the camera state changes,
+///                             cleared when the camera is disconnected
+///   <prefix>/init             rescan the connected cameras
+///   <prefix>/init/response    answers to <prefix>/init (camera/responce while the legacy topics are enabled)
+///   <prefix>/presence         retained online / offline state of the server (see presence.rs)
+///   <prefix>/inventory        retained list of the opened cameras (see inventory.rs)
+///
+/// legacy shared topics (enabled by topics.legacy in the config) :
+///   camera/instr              commands for all cameras, selected by camera_id or camera_idx
+///   camera/responce           answers of all cameras and of camera/init
+///   camera/frame              binary frames of captures started on camera/instr
+///
+/// Answers are published on the topics matching the topic the command came from,
+/// or on the Response Topic of the request with MQTT v5.
+///
+use bytes::Bytes;
+
+// Names kept from the first releases of the server.
+#[allow(non_upper_case_globals)]
+pub const InstrTopic: &str = "camera/instr";
+#[allow(non_upper_case_globals)]
+pub const ResponceTopic: &str = "camera/responce";
+#[allow(non_upper_case_globals)]
+pub const FrameTopic: &str = "camera/frame";
+
+// Topic a command was received on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    Init,
+    Legacy,
+    // camera id or index, as given in the topic
+    Camera(String),
+}
+
+// Topics the answers to a command are published on.
+#[derive(Debug, Clone)]
+pub struct ReplyTopics {
+    pub response: String,
+    pub frame: String,
+    // MQTT v5 Correlation Data of the request, sent back with every answer.
+    pub correlation_data: Option<Bytes>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Topics {
+    prefix: String,
+    legacy: bool,
+}
+
+impl Topics {
+    pub fn new(prefix: &str, legacy: bool) -> Self {
+        Self {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            legacy,
+        }
+    }
+
+    pub fn init_topic(&self) -> String {
+        format!("{}/init", self.prefix)
+    }
+
+    pub fn presence_topic(&self) -> String {
+        format!("{}/presence", self.prefix)
+    }
+
+    pub fn inventory_topic(&self) -> String {
+        format!("{}/inventory", self.prefix)
+    }
+
+    pub fn status_topic(&self, camera_id: &str) -> String {
+        format!("{}/{}/status", self.prefix, camera_id)
+    }
+
+    pub fn subscriptions(&self) -> Vec<String> {
+        let mut topics = vec![format!("{}/+/cmd", self.prefix), self.init_topic()];
+        if self.legacy {
+            topics.push(InstrTopic.to_string());
+        }
+        topics
+    }
+
+    pub fn route(&self, topic: &str) -> Option<Route> {
+        if topic == self.init_topic() {
+            return Some(Route::Init);
+        }
+        if self.legacy && topic == InstrTopic {
+            return Some(Route::Legacy);
+        }
+        let rest = topic.strip_prefix(self.prefix.as_str())?.strip_prefix('/')?;
+        let (camera, leaf) = rest.split_once('/')?;
+        match leaf {
+            "cmd" if !camera.is_empty() => Some(Route::Camera(camera.to_string())),
+            _ => None,
+        }
+    }
+
+    pub fn reply_topics(&self, route: &Route) -> ReplyTopics {
+        match route {
+            Route::Camera(camera) => ReplyTopics {
+                response: format!("{}/{}/response", self.prefix, camera),
+                frame: format!("{}/{}/frame", self.prefix, camera),
+                correlation_data: None,
+            },
+            // Legacy clients expect the init answer with the other answers.
+            Route::Init if !self.legacy => ReplyTopics {
+                response: format!("{}/init/response", self.prefix),
+                frame: FrameTopic.to_string(),
+                correlation_data: None,
+            },
+            Route::Init | Route::Legacy => ReplyTopics {
+                response: ResponceTopic.to_string(),
+                frame: FrameTopic.to_string(),
+                correlation_data: None,
+            },
+        }
+    }
+}
@@ -0,0 +1,341 @@
+///
+/// MQTT client over either protocol version supported by rumqttc.
+///
+/// MQTT 3.1.1 is used by default, MQTT v5 is selected with broker.protocol = "v5".
+/// With v5 the Response Topic and Correlation Data properties of a request are
+/// honoured : the answers are published on the response topic chosen by the
+/// client, carrying the same correlation data, so each client gets its own replies.
+///
+use crate::config::BrokerConfig;
+use bytes::Bytes;
+use log::{debug, info};
+use rumqttc::v5;
+use rumqttc::{AsyncClient, Event, Key, MqttOptions, QoS, Transport};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProtocolVersion {
+    #[default]
+    V4,
+    V5,
+}
+
+impl FromStr for ProtocolVersion {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "v4" | "4" | "3.1.1" => Ok(ProtocolVersion::V4),
+            "v5" | "5" => Ok(ProtocolVersion::V5),
+            _ => Err(format!("unknown MQTT protocol version {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ProtocolVersion::V4 => "v4",
+            ProtocolVersion::V5 => "v5",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Algorithm of the TLS client private key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientKeyType {
+    #[default]
+    Rsa,
+    Ecc,
+}
+
+impl FromStr for ClientKeyType {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rsa" => Ok(ClientKeyType::Rsa),
+            "ecc" => Ok(ClientKeyType::Ecc),
+            _ => Err(format!("unknown client key type {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for ClientKeyType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ClientKeyType::Rsa => "rsa",
+            ClientKeyType::Ecc => "ecc",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug)]
+pub struct TransportError(String);
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for TransportError {}
+impl From<rumqttc::ClientError> for TransportError {
+    fn from(e: rumqttc::ClientError) -> Self {
+        TransportError(e.to_string())
+    }
+}
+impl From<v5::ClientError> for TransportError {
+    fn from(e: v5::ClientError) -> Self {
+        TransportError(e.to_string())
+    }
+}
+impl From<rumqttc::ConnectionError> for TransportError {
+    fn from(e: rumqttc::ConnectionError) -> Self {
+        TransportError(e.to_string())
+    }
+}
+impl From<v5::ConnectionError> for TransportError {
+    fn from(e: v5::ConnectionError) -> Self {
+        TransportError(e.to_string())
+    }
+}
+
+// Last will registered at connection time, published retained by the broker
+// when the connection is lost without a DISCONNECT.
+#[derive(Debug, Clone)]
+pub struct Will {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: QoS,
+}
+
+// What the event loop produced.
+#[derive(Debug, Clone)]
+pub enum Notification {
+    // The connection to the broker is (re)established, subscriptions have to be renewed.
+    Connected,
+    Message(Message),
+    Other,
+}
+
+// A publish received from the broker.
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub topic: String,
+    pub payload: Bytes,
+    // v5 only
+    pub response_topic: Option<String>,
+    pub correlation_data: Option<Bytes>,
+}
+
+fn qos_v5(qos: QoS) -> v5::mqttbytes::QoS {
+    match qos {
+        QoS::AtMostOnce => v5::mqttbytes::QoS::AtMostOnce,
+        QoS::AtLeastOnce => v5::mqttbytes::QoS::AtLeastOnce,
+        QoS::ExactlyOnce => v5::mqttbytes::QoS::ExactlyOnce,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Client {
+    V4(AsyncClient),
+    V5(v5::AsyncClient),
+}
+
+impl Client {
+    pub async fn subscribe(&self, topic: &str, qos: QoS) -> Result<(), TransportError> {
+        match self {
+            Client::V4(client) => client.subscribe(topic, qos).await?,
+            Client::V5(client) => client.subscribe(topic, qos_v5(qos)).await?,
+        }
+        Ok(())
+    }
+
+    // `correlation_data` is only sent with MQTT v5.
+    pub async fn publish(
+        &self,
+        topic: &str,
+        qos: QoS,
+        retain: bool,
+        payload: Vec<u8>,
+        correlation_data: Option<Bytes>,
+    ) -> Result<(), TransportError> {
+        match self {
+            Client::V4(client) => client.publish(topic, qos, retain, payload).await?,
+            Client::V5(client) => {
+                let properties = v5::mqttbytes::v5::PublishProperties {
+                    correlation_data,
+                    ..Default::default()
+                };
+                client
+                    .publish_with_properties(topic, qos_v5(qos), retain, payload, properties)
+                    .await?
+            }
+        }
+        Ok(())
+    }
+}
+
+// Boxed, the event loops are large and differ in size.
+pub enum EventLoop {
+    V4(Box<rumqttc::EventLoop>),
+    V5(Box<v5::EventLoop>),
+}
+
+impl EventLoop {
+    // Drives the connection until the next event.
+    // After an error the next call reconnects to the broker.
+    pub async fn poll(&mut self) -> Result<Notification, TransportError> {
+        match self {
+            EventLoop::V4(eventloop) => match eventloop.poll().await? {
+                Event::Incoming(rumqttc::Packet::ConnAck(_)) => Ok(Notification::Connected),
+                Event::Incoming(rumqttc::Packet::Publish(pkt)) => Ok(Notification::Message(Message {
+                    topic: pkt.topic,
+                    payload: pkt.payload,
+                    response_topic: None,
+                    correlation_data: None,
+                })),
+                Event::Incoming(pkt) => {
+                    debug!("[ MQTTServer] Other packet : {:?}", pkt);
+                    Ok(Notification::Other)
+                }
+                Event::Outgoing(v) => {
+                    info!("[ MQTTServer] Outgoing = {:?}", v);
+                    Ok(Notification::Other)
+                }
+            },
+            EventLoop::V5(eventloop) => match eventloop.poll().await? {
+                v5::Event::Incoming(v5::mqttbytes::v5::Packet::ConnAck(_)) => {
+                    Ok(Notification::Connected)
+                }
+                v5::Event::Incoming(v5::mqttbytes::v5::Packet::Publish(pkt)) => {
+                    let properties = pkt.properties.unwrap_or_default();
+                    Ok(Notification::Message(Message {
+                        topic: String::from_utf8_lossy(&pkt.topic).to_string(),
+                        payload: pkt.payload,
+                        response_topic: properties.response_topic,
+                        correlation_data: properties.correlation_data,
+                    }))
+                }
+                v5::Event::Incoming(pkt) => {
+                    debug!("[ MQTTServer] Other packet : {:?}", pkt);
+                    Ok(Notification::Other)
+                }
+                v5::Event::Outgoing(v) => {
+                    info!("[ MQTTServer] Outgoing = {:?}", v);
+                    Ok(Notification::Other)
+                }
+            },
+        }
+    }
+}
+
+// Exponential delay between two reconnection attempts.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        let min = min.max(Duration::from_millis(100));
+        Self {
+            min,
+            max: max.max(min),
+            next: min,
+        }
+    }
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        delay
+    }
+    pub fn reset(&mut self) {
+        self.next = self.min;
+    }
+}
+
+fn read_pem(path: &Path) -> Result<Vec<u8>, TransportError> {
+    fs::read(path).map_err(|e| TransportError(format!("cannot read {}: {}", path.display(), e)))
+}
+
+// TLS transport from the broker.tls settings, plain TCP without them.
+fn transport(broker: &BrokerConfig) -> Result<Transport, TransportError> {
+    let Some(tls) = &broker.tls else {
+        return Ok(Transport::Tcp);
+    };
+    let ca = read_pem(&tls.ca_file)?;
+    let client_auth = match (&tls.client_cert_file, &tls.client_key_file) {
+        (Some(cert), Some(key)) => {
+            let key = match tls.client_key_type {
+                ClientKeyType::Rsa => Key::RSA(read_pem(key)?),
+                ClientKeyType::Ecc => Key::ECC(read_pem(key)?),
+            };
+            Some((read_pem(cert)?, key))
+        }
+        _ => None,
+    };
+    Ok(Transport::tls(ca, client_auth, None))
+}
+
+// Creates the client and its event loop for the configured protocol version.
+pub fn connect(
+    broker: &BrokerConfig,
+    will: Will,
+    cap: usize,
+) -> Result<(Client, EventLoop), TransportError> {
+    let keep_alive = Duration::from_secs(broker.keep_alive_secs);
+    let transport = transport(broker)?;
+    let credentials = broker
+        .username
+        .as_ref()
+        .map(|user| (user.clone(), broker.password.clone().unwrap_or_default()));
+    match broker.protocol {
+        ProtocolVersion::V4 => {
+            let mut mqttoptions = MqttOptions::new(&broker.client_id, &broker.host, broker.port);
+            mqttoptions.set_keep_alive(keep_alive);
+            let max_packet_size = broker.max_packet_size as usize;
+            mqttoptions.set_max_packet_size(max_packet_size, max_packet_size);
+            if let Some((user, password)) = credentials {
+                mqttoptions.set_credentials(user, password);
+            }
+            mqttoptions.set_transport(transport);
+            mqttoptions.set_last_will(rumqttc::LastWill::new(
+                will.topic,
+                will.payload,
+                will.qos,
+                true,
+            ));
+            let (client, eventloop) = AsyncClient::new(mqttoptions, cap);
+            Ok((Client::V4(client), EventLoop::V4(Box::new(eventloop))))
+        }
+        ProtocolVersion::V5 => {
+            let mut mqttoptions =
+                v5::MqttOptions::new(&broker.client_id, &broker.host, broker.port);
+            mqttoptions.set_keep_alive(keep_alive);
+            mqttoptions.set_max_packet_size(Some(broker.max_packet_size));
+            if let Some((user, password)) = credentials {
+                mqttoptions.set_credentials(user, password);
+            }
+            mqttoptions.set_transport(transport);
+            mqttoptions.set_last_will(v5::mqttbytes::v5::LastWill::new(
+                will.topic,
+                will.payload,
+                qos_v5(will.qos),
+                true,
+                None,
+            ));
+            let (client, eventloop) = v5::AsyncClient::new(mqttoptions, cap);
+            Ok((Client::V5(client), EventLoop::V5(Box::new(eventloop))))
+        }
+    }
+}